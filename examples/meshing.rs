@@ -7,7 +7,10 @@ use bevy::{
         texture::ImageSettings,
     },
 };
-use valve_map::{from_str, meshing::ToMesh};
+use valve_map::{
+    from_str,
+    meshing::{CoordinateSystem, MeshOptions, ToMesh},
+};
 
 fn main() {
     App::new()
@@ -38,30 +41,40 @@ fn startup(
 ) {
     let map = from_str(include_str!("basic.map")).unwrap();
     let worldspawn = &map.entities[0];
-    let entity_mesh = worldspawn.to_mesh().unwrap();
-
-    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
-    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, entity_mesh.positions);
-    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, entity_mesh.normals);
-    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, entity_mesh.uvs);
-    mesh.set_indices(Some(Indices::U32(entity_mesh.indices)));
-
-    commands.spawn_bundle(DirectionalLightBundle {
-        transform: Transform::from_xyz(-2.0, -1.0, 1.0).looking_at(Vec3::ZERO, Vec3::Z),
+    let options = MeshOptions {
+        // Y-up like Bevy, at 32 units per meter
+        coordinates: CoordinateSystem {
+            scale: 1.0 / 32.0,
+            ..CoordinateSystem::Y_UP
+        },
         ..Default::default()
-    });
+    };
 
-    commands.spawn_bundle(PbrBundle {
-        mesh: meshes.add(mesh),
-        material: materials.add(StandardMaterial {
-            base_color_texture: Some(asset_server.load("TECH28.png")),
+    for (texture, entity_mesh) in worldspawn.to_meshes_by_texture_with(&options).unwrap() {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, entity_mesh.positions);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, entity_mesh.normals);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, entity_mesh.uvs);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_TANGENT, entity_mesh.tangents);
+        mesh.set_indices(Some(Indices::U32(entity_mesh.indices)));
+
+        commands.spawn_bundle(PbrBundle {
+            mesh: meshes.add(mesh),
+            material: materials.add(StandardMaterial {
+                base_color_texture: Some(asset_server.load(&format!("{}.png", texture))),
+                ..Default::default()
+            }),
             ..Default::default()
-        }),
+        });
+    }
+
+    commands.spawn_bundle(DirectionalLightBundle {
+        transform: Transform::from_xyz(-2.0, 1.0, 1.0).looking_at(Vec3::ZERO, Vec3::Y),
         ..Default::default()
     });
 
     commands.spawn_bundle(Camera3dBundle {
-        transform: Transform::from_xyz(-300.0, -150.0, 150.0).looking_at(Vec3::ZERO, Vec3::Z),
+        transform: Transform::from_xyz(-9.0, 4.5, 4.5).looking_at(Vec3::ZERO, Vec3::Y),
         ..Default::default()
     });
 }
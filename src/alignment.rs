@@ -0,0 +1,349 @@
+use crate::construction::paraxial_axes;
+use crate::meshing::{face_winding, Epsilons, Plane};
+use crate::{Brush, Face};
+use anyhow::{anyhow, Result};
+use glam::{Quat, Vec3};
+
+/// Texture alignment of the standard Quake format, the world aligned axes of [Face::reset_to_world]
+/// turned by `rotation` degrees.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StandardAlignment {
+    pub offset: [f32; 2],
+    pub rotation: f32,
+    pub scale: [f32; 2],
+}
+
+impl Face {
+    /// Projects the texture along the world axis closest to the normal, with no offset, rotation
+    /// or scaling.
+    pub fn reset_to_world(&mut self) {
+        self.set_axes(paraxial_axes(self.normal()));
+        self.offset = [0.0, 0.0];
+        self.rotation = 0.0;
+        self.scale = [1.0, 1.0];
+    }
+
+    /// Like [Face::reset_to_world] but with the axes lying on the face, so the texture isn't
+    /// stretched on slopes.
+    pub fn reset_to_face(&mut self) {
+        let normal = self.normal();
+        let (u, v) = paraxial_axes(normal);
+        self.set_axes((on_plane(u, normal), on_plane(v, normal)));
+        self.offset = [0.0, 0.0];
+        self.rotation = 0.0;
+        self.scale = [1.0, 1.0];
+    }
+
+    /// Scales and shifts the texture so one copy of it covers the polygon given by `points`,
+    /// keeping the direction of the axes.
+    pub fn fit(&mut self, points: &[[f32; 3]], texture_size: [u32; 2]) {
+        if points.is_empty() {
+            return;
+        }
+
+        let axes = [self.axis_u, self.axis_v].map(|a| Vec3::from_array(a).normalize_or_zero());
+        for (n, axis) in axes.into_iter().enumerate() {
+            let projected = points.iter().map(|p| Vec3::from_array(*p).dot(axis));
+            let min = projected.clone().fold(f32::MAX, f32::min);
+            let max = projected.fold(f32::MIN, f32::max);
+            if max - min <= f32::EPSILON || texture_size[n] == 0 {
+                continue;
+            }
+
+            let scale = self.scale[n].signum() * (max - min) / texture_size[n] as f32;
+            self.scale[n] = scale;
+            self.offset[n] = -(min / scale).min(max / scale);
+        }
+        self.set_axes((axes[0], axes[1]));
+    }
+
+    /// Turns the texture so its U axis runs from `start` to `end`, starting at `start`. The scale
+    /// is kept, and V keeps pointing to the same side of the edge as before.
+    pub fn align_to_edge(&mut self, start: [f32; 3], end: [f32; 3]) {
+        let normal = self.normal();
+        let start = Vec3::from_array(start);
+        let u = on_plane(Vec3::from_array(end) - start, normal);
+        if u == Vec3::ZERO {
+            return;
+        }
+
+        let mut v = u.cross(normal);
+        if v.dot(Vec3::from_array(self.axis_v)) < 0.0 {
+            v = -v;
+        }
+        self.set_axes((u, v));
+        self.offset = [-start.dot(u) / self.scale[0], -start.dot(v) / self.scale[1]];
+    }
+
+    /// Turns the texture axes around the face normal by `degrees`, counter-clockwise when looking
+    /// at the face.
+    pub fn rotate_texture(&mut self, degrees: f32) {
+        let rotation = Quat::from_axis_angle(self.normal(), degrees.to_radians());
+        let [u, v] = [self.axis_u, self.axis_v].map(|a| rotation * Vec3::from_array(a));
+        let rotated = self.rotation + degrees;
+        self.set_axes((u, v));
+        if self.to_standard().is_none() {
+            self.rotation = rotated;
+        }
+    }
+
+    /// The alignment in the standard format, `None` if the axes aren't the world aligned ones
+    /// turned around the projection axis, as on faces aligned with [Face::reset_to_face].
+    pub fn to_standard(&self) -> Option<StandardAlignment> {
+        let (base_u, base_v) = paraxial_axes(self.normal());
+        let (s, t) = (dominant(base_u), dominant(base_v));
+
+        if self.scale.contains(&0.0) {
+            return None;
+        }
+        let u = Vec3::from_array(self.axis_u) / self.scale[0];
+        let v = Vec3::from_array(self.axis_v) / self.scale[1];
+        if u.length() <= f32::EPSILON || v.length() <= f32::EPSILON {
+            return None;
+        }
+
+        // the base U axis is positive along `s`. A negative U scale flips the axis instead of
+        // turning it by 180 degrees, so the rotation is taken from the unscaled axis.
+        let sign = self.scale[0].signum();
+        let rotation = (u[t] * sign).atan2(u[s] * sign);
+        let (base_u, base_v) = (
+            rotate(base_u, s, t, rotation),
+            rotate(base_v, s, t, rotation),
+        );
+        let scale_u = sign / u.length();
+        let scale_v = 1.0 / v.dot(base_v);
+        let tolerance = 1e-3;
+        if !(u * scale_u).abs_diff_eq(base_u, tolerance)
+            || !(v * scale_v).abs_diff_eq(base_v, tolerance)
+        {
+            return None;
+        }
+
+        Some(StandardAlignment {
+            offset: self.offset,
+            rotation: snap_degrees(rotation.to_degrees()),
+            scale: [scale_u, scale_v],
+        })
+    }
+
+    /// Sets the axes from an alignment in the standard format.
+    pub fn set_standard(&mut self, alignment: StandardAlignment) {
+        let (base_u, base_v) = paraxial_axes(self.normal());
+        let (s, t) = (dominant(base_u), dominant(base_v));
+        let angle = alignment.rotation.to_radians();
+        self.set_axes((rotate(base_u, s, t, angle), rotate(base_v, s, t, angle)));
+        self.offset = alignment.offset;
+        self.rotation = alignment.rotation;
+        self.scale = alignment.scale;
+    }
+
+    fn normal(&self) -> Vec3 {
+        Plane::from_triangle(self.triangle)
+            .map(|plane| plane.normal.as_vec3())
+            .unwrap_or(Vec3::Z)
+    }
+
+    fn set_axes(&mut self, (u, v): (Vec3, Vec3)) {
+        self.axis_u = u.to_array();
+        self.axis_v = v.to_array();
+        if let Some(standard) = self.to_standard() {
+            self.rotation = standard.rotation;
+        }
+    }
+}
+
+impl Brush {
+    /// Fits the texture of face `face` to its polygon, see [Face::fit].
+    pub fn fit_texture(&mut self, face: usize, texture_size: [u32; 2]) -> Result<()> {
+        self.fit_texture_with(face, texture_size, &Epsilons::default())
+    }
+
+    pub fn fit_texture_with(
+        &mut self,
+        face: usize,
+        texture_size: [u32; 2],
+        epsilons: &Epsilons,
+    ) -> Result<()> {
+        if face >= self.faces.len() {
+            return Err(anyhow!("the brush has no face {}", face));
+        }
+        let planes = self.planes()?;
+        let points = face_winding(&planes, face, epsilons)
+            .ok_or_else(|| anyhow!("face {} has no area", face))?
+            .iter()
+            .map(|p| p.as_vec3().to_array())
+            .collect::<Vec<_>>();
+
+        self.faces[face].fit(&points, texture_size);
+        Ok(())
+    }
+}
+
+/// Turns `vector` by `angle` in the plane of the components `s` and `t`, the way Quake turns
+/// standard texture axes.
+fn rotate(vector: Vec3, s: usize, t: usize, angle: f32) -> Vec3 {
+    let (sin, cos) = match snap_degrees(angle.to_degrees()).rem_euclid(360.0) {
+        0.0 => (0.0, 1.0),
+        90.0 => (1.0, 0.0),
+        180.0 => (0.0, -1.0),
+        270.0 => (-1.0, 0.0),
+        _ => angle.sin_cos(),
+    };
+    let mut rotated = vector;
+    rotated[s] = cos * vector[s] - sin * vector[t];
+    rotated[t] = sin * vector[s] + cos * vector[t];
+    rotated
+}
+
+/// Index of the largest component of a world aligned axis.
+fn dominant(axis: Vec3) -> usize {
+    let abs = axis.abs();
+    if abs.x >= abs.y && abs.x >= abs.z {
+        0
+    } else if abs.y >= abs.z {
+        1
+    } else {
+        2
+    }
+}
+
+fn on_plane(vector: Vec3, normal: Vec3) -> Vec3 {
+    (vector - normal * normal.dot(vector)).normalize_or_zero()
+}
+
+fn snap_degrees(degrees: f32) -> f32 {
+    let rounded = (degrees * 1000.0).round() / 1000.0;
+    rounded + 0.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn floor() -> Face {
+        Brush::cuboid([0.0, 0.0, 0.0], [32.0, 64.0, 16.0], "FLOOR")
+            .unwrap()
+            .faces
+            .into_iter()
+            .find(|face| face.normal() == Vec3::Z)
+            .unwrap()
+    }
+
+    fn assert_axes(face: &Face, u: [f32; 3], v: [f32; 3]) {
+        assert!(Vec3::from_array(face.axis_u).abs_diff_eq(Vec3::from_array(u), 1e-5));
+        assert!(Vec3::from_array(face.axis_v).abs_diff_eq(Vec3::from_array(v), 1e-5));
+    }
+
+    #[test]
+    fn test_reset() {
+        let slope = Brush::wedge([0.0, 0.0, 0.0], [64.0, 32.0, 64.0], "RAMP")
+            .unwrap()
+            .faces
+            .remove(4);
+
+        let mut face = Face {
+            offset: [3.0, 4.0],
+            scale: [2.0, 2.0],
+            ..slope
+        };
+        face.reset_to_world();
+        assert_axes(&face, [1.0, 0.0, 0.0], [0.0, -1.0, 0.0]);
+        assert_eq!((face.offset, face.scale), ([0.0, 0.0], [1.0, 1.0]));
+
+        face.reset_to_face();
+        let half = 0.5f32.sqrt();
+        assert_axes(&face, [half, 0.0, half], [0.0, -1.0, 0.0]);
+        assert_eq!(face.to_standard(), None);
+    }
+
+    #[test]
+    fn test_fit() {
+        let mut brush = Brush::cuboid([0.0, 0.0, 0.0], [32.0, 64.0, 16.0], "FLOOR").unwrap();
+        let top = brush
+            .faces
+            .iter()
+            .position(|face| face.normal() == Vec3::Z)
+            .unwrap();
+        brush.fit_texture(top, [64, 64]).unwrap();
+
+        let face = &brush.faces[top];
+        assert_eq!(face.scale, [0.5, 1.0]);
+        // u = x / 0.5 runs from 0 to 64, v = -y runs from -64 to 0
+        assert_eq!(face.offset, [0.0, 64.0]);
+
+        assert!(brush.fit_texture(6, [64, 64]).is_err());
+    }
+
+    #[test]
+    fn test_align_to_edge() {
+        let mut face = floor();
+        face.scale = [2.0, 2.0];
+        face.align_to_edge([32.0, 0.0, 16.0], [32.0, 64.0, 16.0]);
+        assert_axes(&face, [0.0, 1.0, 0.0], [1.0, 0.0, 0.0]);
+        assert_eq!(face.offset, [0.0, -16.0]);
+        assert_eq!(face.scale, [2.0, 2.0]);
+        assert_eq!(face.rotation, 90.0);
+    }
+
+    #[test]
+    fn test_rotate_texture() {
+        let mut face = floor();
+        face.rotate_texture(90.0);
+        assert_axes(&face, [0.0, 1.0, 0.0], [1.0, 0.0, 0.0]);
+        assert_eq!(face.rotation, 90.0);
+
+        face.rotate_texture(-120.0);
+        assert_eq!(face.rotation, -30.0);
+    }
+
+    #[test]
+    fn test_standard() {
+        let mut face = floor();
+        let alignment = face.to_standard().unwrap();
+        assert_eq!(
+            alignment,
+            StandardAlignment {
+                offset: [0.0, 0.0],
+                rotation: 0.0,
+                scale: [1.0, 1.0],
+            }
+        );
+
+        let alignment = StandardAlignment {
+            offset: [8.0, -4.0],
+            rotation: 30.0,
+            scale: [2.0, -0.5],
+        };
+        face.set_standard(alignment);
+        let round_trip = face.to_standard().unwrap();
+        assert_eq!(round_trip.offset, alignment.offset);
+        assert!((round_trip.rotation - 30.0).abs() < 1e-3);
+        assert!((round_trip.scale[0] - 2.0).abs() < 1e-5);
+        assert!((round_trip.scale[1] + 0.5).abs() < 1e-5);
+
+        // Quake's quarter turn of a wall facing X
+        let mut wall = Brush::cuboid([0.0; 3], [16.0; 3], "WALL")
+            .unwrap()
+            .faces
+            .remove(1);
+        wall.set_standard(StandardAlignment {
+            rotation: 90.0,
+            ..alignment
+        });
+        assert_axes(&wall, [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]);
+
+        // a flipped U axis keeps its negative scale instead of turning by 180 degrees
+        for rotation in [0.0, 30.0] {
+            let flipped = StandardAlignment {
+                offset: [0.0, 0.0],
+                rotation,
+                scale: [-1.0, 1.0],
+            };
+            face.set_standard(flipped);
+            let round_trip = face.to_standard().unwrap();
+            assert!((round_trip.rotation - rotation).abs() < 1e-3);
+            assert!((round_trip.scale[0] + 1.0).abs() < 1e-5);
+            assert!((round_trip.scale[1] - 1.0).abs() < 1e-5);
+        }
+    }
+}
@@ -0,0 +1,643 @@
+//! Compiles the world brushes of a map into a solid/empty BSP tree, and finds leaks with it.
+
+use crate::geometry::face_windings;
+use crate::meshing::{face_winding, seals, Epsilons, MeshOptions, Plane};
+use crate::{Aabb, Brush, Map};
+use anyhow::{Context, Result};
+use glam::{DVec3, Vec3};
+use std::collections::VecDeque;
+
+/// Space left between the brushes and the bounds of the tree, so the void around the map is
+/// made of empty leaves.
+const BOUNDS_MARGIN: f64 = 64.0;
+/// Faces considered as splitting planes at each node.
+const SPLIT_CANDIDATES: usize = 64;
+/// Weight of a split face against the imbalance of the two sides when choosing a plane.
+const SPLIT_COST: usize = 5;
+
+/// What fills a leaf.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Contents {
+    Empty,
+    Solid,
+}
+
+/// An index into [BspTree::nodes] or [BspTree::leaves].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BspChild {
+    Node(usize),
+    Leaf(usize),
+}
+
+/// Splits its region by `plane`, the front child is the side the normal points to.
+#[derive(Debug, Clone)]
+pub struct BspNode {
+    pub plane: Plane,
+    pub front: BspChild,
+    pub back: BspChild,
+}
+
+/// A convex region of the tree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BspLeaf {
+    pub contents: Contents,
+    /// Whether the void around the map can be reached from this empty leaf without passing a
+    /// solid one. In a sealed map only the leaves around it are.
+    pub outside: bool,
+    /// The portals of the leaf.
+    pub portals: Vec<usize>,
+}
+
+/// The opening between two adjacent leaves.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Portal {
+    /// The second leaf is `None` for portals on the bounds of the tree, which lead to the void.
+    pub leaves: (usize, Option<usize>),
+    pub winding: Vec<[f32; 3]>,
+}
+
+/// A path from a point entity through empty leaves to the void, which keeps the map from being
+/// sealed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Leak {
+    pub entity: usize,
+    /// Starts at the origin of the entity, passes the centers of the portals in between and ends
+    /// on the bounds of the tree.
+    pub path: Vec<[f32; 3]>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BspTree {
+    pub nodes: Vec<BspNode>,
+    pub leaves: Vec<BspLeaf>,
+    pub portals: Vec<Portal>,
+    pub root: BspChild,
+    /// The box the leaves fill, the brushes with a margin around them.
+    pub bounds: Aabb,
+}
+
+/// A face of the union of the brushes, wound counter-clockwise around its outward normal.
+#[derive(Debug, Clone)]
+struct Side {
+    plane: Plane,
+    winding: Vec<DVec3>,
+}
+
+impl BspTree {
+    /// Builds the tree from the brushes of the `worldspawn` and `func_group` entities that seal
+    /// the map, see [seals].
+    pub fn from_map(map: &Map) -> Result<Self> {
+        Self::from_map_with(map, &MeshOptions::default())
+    }
+
+    pub fn from_map_with(map: &Map, options: &MeshOptions) -> Result<Self> {
+        let mut brushes = Vec::new();
+        for (i, entity) in map.entities.iter().enumerate() {
+            let classname = entity.properties.get("classname").map(String::as_str);
+            if !matches!(classname, Some("worldspawn") | Some("func_group")) {
+                continue;
+            }
+            for (j, brush) in entity.brushes.iter().enumerate() {
+                if seals(brush, options) {
+                    let sides = brush_sides(brush, &options.epsilons)
+                        .with_context(|| format!("entity {}, brush {}", i, j))?;
+                    brushes.push(sides);
+                }
+            }
+        }
+
+        let epsilon = options.epsilons.plane as f64;
+        let sides = union_sides(&brushes, epsilon);
+        let (min, max) = brushes
+            .iter()
+            .flatten()
+            .flat_map(|side| &side.winding)
+            .fold(
+                (DVec3::splat(f64::MAX), DVec3::splat(f64::MIN)),
+                |(min, max), p| (min.min(*p), max.max(*p)),
+            );
+        let (min, max) = if brushes.is_empty() {
+            (DVec3::ZERO, DVec3::ZERO)
+        } else {
+            (min, max)
+        };
+
+        let mut tree = Self {
+            nodes: Vec::new(),
+            leaves: Vec::new(),
+            portals: Vec::new(),
+            root: BspChild::Leaf(0),
+            bounds: Aabb {
+                min: (min - BOUNDS_MARGIN).as_vec3().to_array(),
+                max: (max + BOUNDS_MARGIN).as_vec3().to_array(),
+            },
+        };
+        tree.root = tree.build(sides, Contents::Empty, epsilon);
+        tree.make_portals(&options.epsilons);
+        tree.fill_outside();
+        Ok(tree)
+    }
+
+    /// The leaf containing `point`. Points on a splitting plane count as in front of it.
+    pub fn leaf_at(&self, point: [f32; 3]) -> usize {
+        let point = Vec3::from_array(point).as_dvec3();
+        let mut child = self.root;
+        loop {
+            match child {
+                BspChild::Leaf(leaf) => return leaf,
+                BspChild::Node(n) => {
+                    let node = &self.nodes[n];
+                    child = if node.plane.distance(point) >= 0.0 {
+                        node.front
+                    } else {
+                        node.back
+                    };
+                }
+            }
+        }
+    }
+
+    pub fn contents(&self, point: [f32; 3]) -> Contents {
+        self.leaves[self.leaf_at(point)].contents
+    }
+
+    /// The first point entity of `map` that can reach the void, with the shortest path there
+    /// in portals. `None` if the map is sealed.
+    pub fn find_leak(&self, map: &Map) -> Option<Leak> {
+        let (entity, origin) = map.entities.iter().enumerate().find_map(|(i, entity)| {
+            if !entity.brushes.is_empty() {
+                return None;
+            }
+            let origin = entity.vector("origin")?;
+            self.leaves[self.leaf_at(origin)]
+                .outside
+                .then_some((i, origin))
+        })?;
+
+        // search outwards from the entity until a portal to the void is found
+        let start = self.leaf_at(origin);
+        let mut reached_by = vec![None; self.leaves.len()];
+        let mut queue = VecDeque::from([start]);
+        let mut visited = vec![false; self.leaves.len()];
+        visited[start] = true;
+        while let Some(leaf) = queue.pop_front() {
+            for &p in &self.leaves[leaf].portals {
+                let next = match self.other_leaf(p, leaf) {
+                    None => {
+                        let mut path = vec![self.portal_center(p)];
+                        let mut current = leaf;
+                        while let Some((portal, previous)) = reached_by[current] {
+                            path.push(self.portal_center(portal));
+                            current = previous;
+                        }
+                        path.push(origin);
+                        path.reverse();
+                        return Some(Leak { entity, path });
+                    }
+                    Some(next) => next,
+                };
+                if !visited[next] && self.leaves[next].contents == Contents::Empty {
+                    visited[next] = true;
+                    reached_by[next] = Some((p, leaf));
+                    queue.push_back(next);
+                }
+            }
+        }
+        None
+    }
+
+    /// Builds the subtree for the region bounded by `sides`, which becomes a leaf with
+    /// `contents` if there are none.
+    fn build(&mut self, sides: Vec<Side>, contents: Contents, epsilon: f64) -> BspChild {
+        if sides.is_empty() {
+            self.leaves.push(BspLeaf {
+                contents,
+                outside: false,
+                portals: Vec::new(),
+            });
+            return BspChild::Leaf(self.leaves.len() - 1);
+        }
+
+        let plane = choose_plane(&sides, epsilon);
+        let (mut front, mut back) = (Vec::new(), Vec::new());
+        for side in sides {
+            if is_on(&side.winding, &plane, epsilon) {
+                // faces on the plane are done, the ones facing the other way still separate
+                // solid space in front of it
+                if side.plane.normal.dot(plane.normal) < 0.0 {
+                    front.push(side);
+                }
+                continue;
+            }
+            let (in_front, behind) = split(&side.winding, &plane, epsilon);
+            front.extend(in_front.map(|winding| Side {
+                plane: side.plane.clone(),
+                winding,
+            }));
+            back.extend(behind.map(|winding| Side {
+                plane: side.plane.clone(),
+                winding,
+            }));
+        }
+
+        let n = self.nodes.len();
+        self.nodes.push(BspNode {
+            plane,
+            front: BspChild::Leaf(0),
+            back: BspChild::Leaf(0),
+        });
+        self.nodes[n].front = self.build(front, Contents::Empty, epsilon);
+        self.nodes[n].back = self.build(back, Contents::Solid, epsilon);
+        BspChild::Node(n)
+    }
+
+    /// Finds the openings between leaves. Every node contributes the part of its plane inside
+    /// its region, cut up where it meets leaves on both sides.
+    fn make_portals(&mut self, epsilons: &Epsilons) {
+        let (min, max) = (
+            Vec3::from_array(self.bounds.min).as_dvec3(),
+            Vec3::from_array(self.bounds.max).as_dvec3(),
+        );
+        let mut region = [DVec3::X, DVec3::Y, DVec3::Z]
+            .into_iter()
+            .flat_map(|axis| [Plane::new(min, -axis), Plane::new(max, axis)])
+            .collect::<Vec<_>>();
+
+        // the sides of the bounds lead to the void
+        for i in 0..region.len() {
+            if let Some(winding) = face_winding(&region, i, epsilons) {
+                let inward = -region[i].normal;
+                for (leaf, piece) in self.distribute(self.root, winding, inward, epsilons) {
+                    self.add_portal(leaf, None, piece);
+                }
+            }
+        }
+
+        self.node_portals(self.root, &mut region, epsilons);
+    }
+
+    /// Adds the portals of the nodes of the subtree at `child`, whose region is bounded by
+    /// `region`.
+    fn node_portals(&mut self, child: BspChild, region: &mut Vec<Plane>, epsilons: &Epsilons) {
+        let node = match child {
+            BspChild::Node(n) => self.nodes[n].clone(),
+            BspChild::Leaf(_) => return,
+        };
+
+        region.push(node.plane.clone());
+        if let Some(winding) = face_winding(region, region.len() - 1, epsilons) {
+            let normal = node.plane.normal;
+            for (front, piece) in self.distribute(node.front, winding, normal, epsilons) {
+                for (back, piece) in self.distribute(node.back, piece, -normal, epsilons) {
+                    self.add_portal(front, Some(back), piece);
+                }
+            }
+        }
+        self.node_portals(node.back, region, epsilons);
+        region.pop();
+
+        // the front region is bounded by the flipped plane
+        region.push(Plane::new(node.plane.origin, -node.plane.normal));
+        self.node_portals(node.front, region, epsilons);
+        region.pop();
+    }
+
+    /// Splits `winding`, which lies on the plane of an ancestor, into the leaves of `child`.
+    /// Where it lies on a plane of the subtree it goes to the side `into` points to.
+    fn distribute(
+        &self,
+        child: BspChild,
+        winding: Vec<DVec3>,
+        into: DVec3,
+        epsilons: &Epsilons,
+    ) -> Vec<(usize, Vec<DVec3>)> {
+        let epsilon = epsilons.plane as f64;
+        let mut pieces = Vec::new();
+        let mut stack = vec![(child, winding)];
+        while let Some((child, winding)) = stack.pop() {
+            let n = match child {
+                BspChild::Leaf(leaf) => {
+                    pieces.push((leaf, winding));
+                    continue;
+                }
+                BspChild::Node(n) => n,
+            };
+            let node = &self.nodes[n];
+            if is_on(&winding, &node.plane, epsilon) {
+                if node.plane.normal.dot(into) > 0.0 {
+                    stack.push((node.front, winding));
+                } else {
+                    stack.push((node.back, winding));
+                }
+                continue;
+            }
+
+            let (front, back) = split(&winding, &node.plane, epsilon);
+            let vertex = epsilons.vertex as f64;
+            stack.extend(
+                front
+                    .filter(|w| area(w) > vertex * vertex)
+                    .map(|w| (node.front, w)),
+            );
+            stack.extend(
+                back.filter(|w| area(w) > vertex * vertex)
+                    .map(|w| (node.back, w)),
+            );
+        }
+        pieces
+    }
+
+    fn add_portal(&mut self, leaf: usize, other: Option<usize>, winding: Vec<DVec3>) {
+        let p = self.portals.len();
+        self.portals.push(Portal {
+            leaves: (leaf, other),
+            winding: winding.iter().map(|v| v.as_vec3().to_array()).collect(),
+        });
+        self.leaves[leaf].portals.push(p);
+        if let Some(other) = other {
+            self.leaves[other].portals.push(p);
+        }
+    }
+
+    /// Marks the empty leaves reachable from the void.
+    fn fill_outside(&mut self) {
+        let mut queue = VecDeque::new();
+        for portal in &self.portals {
+            if let (leaf, None) = portal.leaves {
+                queue.push_back(leaf);
+            }
+        }
+        while let Some(leaf) = queue.pop_front() {
+            if self.leaves[leaf].outside || self.leaves[leaf].contents == Contents::Solid {
+                continue;
+            }
+            self.leaves[leaf].outside = true;
+            for &p in &self.leaves[leaf].portals {
+                queue.extend(self.other_leaf(p, leaf));
+            }
+        }
+    }
+
+    /// The leaf on the other side of portal `p`, `None` for the void.
+    fn other_leaf(&self, p: usize, leaf: usize) -> Option<usize> {
+        let (a, b) = self.portals[p].leaves;
+        if a == leaf {
+            b
+        } else {
+            Some(a)
+        }
+    }
+
+    fn portal_center(&self, p: usize) -> [f32; 3] {
+        let winding = &self.portals[p].winding;
+        let sum = winding
+            .iter()
+            .fold(Vec3::ZERO, |sum, v| sum + Vec3::from_array(*v));
+        (sum / winding.len() as f32).to_array()
+    }
+}
+
+fn brush_sides(brush: &Brush, epsilons: &Epsilons) -> Result<Vec<Side>> {
+    let planes = brush.planes()?;
+    let windings = face_windings(&planes, epsilons)?;
+    Ok(windings
+        .into_iter()
+        .map(|(i, winding)| Side {
+            plane: planes[i].clone(),
+            winding,
+        })
+        .collect())
+}
+
+/// The faces of the union of the brushes: the parts of every face outside all other brushes.
+/// Of faces on the same plane facing the same way the first brush keeps its own, faces touching
+/// another brush from outside are dropped.
+fn union_sides(brushes: &[Vec<Side>], epsilon: f64) -> Vec<Side> {
+    let bounds = brushes
+        .iter()
+        .map(|brush| {
+            brush.iter().flat_map(|side| &side.winding).fold(
+                (DVec3::splat(f64::MAX), DVec3::splat(f64::MIN)),
+                |(min, max), p| (min.min(*p), max.max(*p)),
+            )
+        })
+        .collect::<Vec<_>>();
+    // brushes only cut each other's faces where their bounds overlap or touch
+    let near = |a: usize, b: usize| {
+        let ((min_a, max_a), (min_b, max_b)) = (bounds[a], bounds[b]);
+        min_a.cmple(max_b + epsilon).all() && min_b.cmple(max_a + epsilon).all()
+    };
+
+    let mut sides = Vec::new();
+    for (a, brush) in brushes.iter().enumerate() {
+        let others = (0..brushes.len())
+            .filter(|b| *b != a && near(a, *b))
+            .collect::<Vec<_>>();
+        for side in brush {
+            let mut pieces = vec![side.winding.clone()];
+            for (b, other) in others.iter().map(|b| (*b, &brushes[*b])) {
+                pieces = pieces
+                    .into_iter()
+                    .flat_map(|winding| outside_of(winding, &side.plane, a < b, other, epsilon))
+                    .collect();
+            }
+            sides.extend(pieces.into_iter().map(|winding| Side {
+                plane: side.plane.clone(),
+                winding,
+            }));
+        }
+    }
+    sides
+}
+
+/// The parts of `winding` outside the convex brush `other`.
+fn outside_of(
+    winding: Vec<DVec3>,
+    plane: &Plane,
+    keep_shared: bool,
+    other: &[Side],
+    epsilon: f64,
+) -> Vec<Vec<DVec3>> {
+    let mut outside = Vec::new();
+    let mut inside = winding;
+    for side in other {
+        if is_on(&inside, &side.plane, epsilon) {
+            if keep_shared && side.plane.normal.dot(plane.normal) > 0.0 {
+                // the face is shared with `other`, and kept on this brush
+                outside.push(inside);
+                return outside;
+            }
+            continue;
+        }
+        let (front, back) = split(&inside, &side.plane, epsilon);
+        outside.extend(front);
+        match back {
+            Some(back) => inside = back,
+            None => return outside,
+        }
+    }
+    outside
+}
+
+/// Picks the plane of a face that splits the fewest other faces and leaves the two sides most
+/// balanced.
+fn choose_plane(sides: &[Side], epsilon: f64) -> Plane {
+    let step = sides.len().div_ceil(SPLIT_CANDIDATES);
+    sides
+        .iter()
+        .step_by(step)
+        .min_by_key(|candidate| {
+            let (mut front, mut back, mut splits) = (0usize, 0usize, 0);
+            for side in sides {
+                let distances = side.winding.iter().map(|p| candidate.plane.distance(*p));
+                let (min, max) = distances.fold((f64::MAX, f64::MIN), |(min, max), d| {
+                    (min.min(d), max.max(d))
+                });
+                if min < -epsilon && max > epsilon {
+                    splits += 1;
+                } else if max > epsilon {
+                    front += 1;
+                } else if min < -epsilon {
+                    back += 1;
+                }
+            }
+            splits * SPLIT_COST + front.abs_diff(back)
+        })
+        .map(|side| side.plane.clone())
+        .unwrap()
+}
+
+fn is_on(winding: &[DVec3], plane: &Plane, epsilon: f64) -> bool {
+    winding.iter().all(|p| plane.distance(*p).abs() <= epsilon)
+}
+
+/// Splits `winding` into the parts in front of and behind `plane`.
+fn split(
+    winding: &[DVec3],
+    plane: &Plane,
+    epsilon: f64,
+) -> (Option<Vec<DVec3>>, Option<Vec<DVec3>>) {
+    let distances = winding
+        .iter()
+        .map(|p| plane.distance(*p))
+        .collect::<Vec<_>>();
+    if distances.iter().all(|d| *d >= -epsilon) {
+        return (Some(winding.to_vec()), None);
+    }
+    if distances.iter().all(|d| *d <= epsilon) {
+        return (None, Some(winding.to_vec()));
+    }
+
+    let (mut front, mut back) = (Vec::new(), Vec::new());
+    for (n, a) in winding.iter().enumerate() {
+        let m = (n + 1) % winding.len();
+        let (da, db) = (distances[n], distances[m]);
+        if da >= -epsilon {
+            front.push(*a);
+        }
+        if da <= epsilon {
+            back.push(*a);
+        }
+        if (da > epsilon && db < -epsilon) || (da < -epsilon && db > epsilon) {
+            let point = *a + (winding[m] - *a) * (da / (da - db));
+            front.push(point);
+            back.push(point);
+        }
+    }
+    let valid = |w: Vec<DVec3>| (w.len() >= 3).then_some(w);
+    (valid(front), valid(back))
+}
+
+fn area(winding: &[DVec3]) -> f64 {
+    let mut cross = DVec3::ZERO;
+    for n in 1..winding.len().saturating_sub(1) {
+        cross += (winding[n] - winding[0]).cross(winding[n + 1] - winding[0]);
+    }
+    cross.length() * 0.5
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MapBuilder;
+
+    /// A hollow box around the space from (-128, -128, 0) to (128, 128, 128), with walls
+    /// overlapping at the edges. The wall on the positive x side gets `east_wall` as its texture,
+    /// or is left out.
+    fn room(east_wall: Option<&str>) -> Map {
+        let mut builder = MapBuilder::new()
+            .brush(Brush::cuboid([-144.0, -144.0, -16.0], [144.0, 144.0, 0.0], "FLOOR").unwrap())
+            .brush(Brush::cuboid([-144.0, -144.0, 128.0], [144.0, 144.0, 144.0], "SKY1").unwrap())
+            .brush(Brush::cuboid([-144.0, -144.0, -16.0], [-128.0, 144.0, 144.0], "WALL").unwrap())
+            .brush(Brush::cuboid([-144.0, -144.0, -16.0], [144.0, -128.0, 144.0], "WALL").unwrap())
+            .brush(Brush::cuboid([-144.0, 128.0, -16.0], [144.0, 144.0, 144.0], "WALL").unwrap())
+            // a pillar that's not part of the seal
+            .brush(Brush::cuboid([-16.0, -16.0, 0.0], [16.0, 16.0, 128.0], "PILLAR").unwrap());
+        if let Some(texture) = east_wall {
+            builder = builder.brush(
+                Brush::cuboid([128.0, -144.0, -16.0], [144.0, 144.0, 144.0], texture).unwrap(),
+            );
+        }
+        builder
+            .point_entity("light", [0.0, 64.0, 100.0], &[])
+            .point_entity("info_player_start", [-64.0, -64.0, 24.0], &[])
+            .build()
+    }
+
+    #[test]
+    fn test_contents() {
+        let tree = BspTree::from_map(&room(Some("WALL"))).unwrap();
+        assert_eq!(tree.contents([-64.0, -64.0, 24.0]), Contents::Empty);
+        assert_eq!(tree.contents([0.0, 0.0, 64.0]), Contents::Solid);
+        assert_eq!(tree.contents([0.0, 100.0, -8.0]), Contents::Solid);
+        // where the walls overlap
+        assert_eq!(tree.contents([-140.0, -140.0, 64.0]), Contents::Solid);
+        assert_eq!(tree.contents([0.0, 0.0, 500.0]), Contents::Empty);
+
+        for leaf in &tree.leaves {
+            if leaf.contents == Contents::Empty {
+                assert!(!leaf.portals.is_empty());
+            }
+        }
+        assert!(tree.leaves[tree.leaf_at([0.0, 0.0, 500.0])].outside);
+        assert!(!tree.leaves[tree.leaf_at([-64.0, -64.0, 24.0])].outside);
+    }
+
+    #[test]
+    fn test_sealed() {
+        let map = room(Some("WALL"));
+        let tree = BspTree::from_map(&map).unwrap();
+        assert_eq!(tree.find_leak(&map), None);
+    }
+
+    #[test]
+    fn test_leak() {
+        for east_wall in [None, Some("CLIP")] {
+            let map = room(east_wall);
+            let tree = BspTree::from_map(&map).unwrap();
+            let leak = tree.find_leak(&map).unwrap();
+            assert_eq!(leak.entity, 1);
+            assert_eq!(leak.path[0], [0.0, 64.0, 100.0]);
+
+            // the path ends on the bounds, and leaves through the opening
+            let end = *leak.path.last().unwrap();
+            assert!((0..3).any(|i| end[i] == tree.bounds.min[i] || end[i] == tree.bounds.max[i]));
+            let crossing = leak.path.windows(2).find_map(|segment| {
+                let [a, b] = [segment[0], segment[1]].map(Vec3::from_array);
+                let t = (136.0 - a.x) / (b.x - a.x);
+                (0.0..=1.0).contains(&t).then(|| a.lerp(b, t))
+            });
+            let crossing = crossing.unwrap();
+            assert!(crossing.y.abs() < 128.0 && crossing.z > 0.0 && crossing.z < 128.0);
+        }
+    }
+
+    #[test]
+    fn test_empty_map() {
+        let map = MapBuilder::new().build();
+        let tree = BspTree::from_map(&map).unwrap();
+        assert_eq!(tree.root, BspChild::Leaf(0));
+        assert_eq!(tree.contents([0.0; 3]), Contents::Empty);
+        assert_eq!(tree.portals.len(), 6);
+    }
+}
@@ -0,0 +1,210 @@
+use crate::geometry::face_windings;
+use crate::meshing::{Epsilons, MeshOptions, TextureClass};
+use crate::{Brush, Entity, Map};
+use anyhow::{Context, Result};
+use glam::DVec3;
+
+/// The convex shape of a solid brush, for physics engines that take convex hulls.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConvexHull {
+    pub entity: usize,
+    pub brush: usize,
+    /// Corners of the brush, without duplicates.
+    pub vertices: Vec<[f32; 3]>,
+    /// Outward facing planes `[x, y, z, d]` of the faces, where points on a plane satisfy
+    /// `normal · point = d`.
+    pub planes: Vec<[f32; 4]>,
+}
+
+impl Brush {
+    /// Whether the brush should collide, see [TextureClass::is_solid]. Brushes with a liquid
+    /// face or only with faces like `skip` or `trigger` don't.
+    pub fn is_solid(&self) -> bool {
+        self.is_solid_with(&MeshOptions::default())
+    }
+
+    pub fn is_solid_with(&self, options: &MeshOptions) -> bool {
+        let classes = self
+            .faces
+            .iter()
+            .map(|face| options.texture_class(&face.texture_name))
+            .collect::<Vec<_>>();
+
+        !classes.contains(&TextureClass::Liquid) && classes.iter().any(TextureClass::is_solid)
+    }
+
+    /// Computes the corners and planes of the brush. `entity` and `brush` are set to 0, use
+    /// [Map::collision_hulls] to have them filled in.
+    pub fn convex_hull(&self) -> Result<ConvexHull> {
+        self.convex_hull_with(&Epsilons::default())
+    }
+
+    pub fn convex_hull_with(&self, epsilons: &Epsilons) -> Result<ConvexHull> {
+        let planes = self.planes()?;
+        let windings = face_windings(&planes, epsilons)?;
+
+        let mut hull = ConvexHull {
+            entity: 0,
+            brush: 0,
+            vertices: Vec::new(),
+            planes: Vec::new(),
+        };
+        let mut vertices: Vec<DVec3> = Vec::new();
+        for (i, winding) in windings {
+            let plane = &planes[i];
+            let [x, y, z] = plane.normal.as_vec3().to_array();
+            hull.planes
+                .push([x, y, z, plane.normal.dot(plane.origin) as f32]);
+            for point in winding {
+                if vertices
+                    .iter()
+                    .all(|v| v.distance(point) > epsilons.vertex as f64)
+                {
+                    vertices.push(point);
+                }
+            }
+        }
+
+        hull.vertices = vertices.iter().map(|v| v.as_vec3().to_array()).collect();
+        Ok(hull)
+    }
+}
+
+impl Entity {
+    /// Convex hulls of the solid brushes of the entity, see [Brush::is_solid]. The hulls have
+    /// `entity` set to 0. Brushes that don't enclose a volume are left out, unless
+    /// [MeshOptions::strict] is set.
+    pub fn collision_hulls(&self) -> Result<Vec<ConvexHull>> {
+        self.collision_hulls_with(&MeshOptions::default())
+    }
+
+    pub fn collision_hulls_with(&self, options: &MeshOptions) -> Result<Vec<ConvexHull>> {
+        let mut hulls = Vec::new();
+        for (i, brush) in self.brushes.iter().enumerate() {
+            if !brush.is_solid_with(options) {
+                continue;
+            }
+            match brush.convex_hull_with(&options.epsilons) {
+                Ok(hull) => hulls.push(ConvexHull { brush: i, ..hull }),
+                Err(err) if options.strict => return Err(err.context(format!("brush {}", i))),
+                Err(_) => continue,
+            }
+        }
+        Ok(hulls)
+    }
+}
+
+impl Map {
+    /// Convex hulls of all solid brushes in the map, see [Brush::is_solid].
+    pub fn collision_hulls(&self) -> Result<Vec<ConvexHull>> {
+        self.collision_hulls_with(&MeshOptions::default())
+    }
+
+    pub fn collision_hulls_with(&self, options: &MeshOptions) -> Result<Vec<ConvexHull>> {
+        let mut hulls = Vec::new();
+        for (i, entity) in self.entities.iter().enumerate() {
+            let entity_hulls = entity
+                .collision_hulls_with(options)
+                .with_context(|| format!("entity {}", i))?;
+            hulls.extend(
+                entity_hulls
+                    .into_iter()
+                    .map(|hull| ConvexHull { entity: i, ..hull }),
+            );
+        }
+        Ok(hulls)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_str;
+
+    fn cube(textures: [&str; 6]) -> String {
+        format!(
+            r#"{{
+( -16 -16 -16 ) ( -16 -15 -16 ) ( -16 -16 -15 ) {} [ 0 -1 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( -16 -16 -16 ) ( -16 -16 -15 ) ( -15 -16 -16 ) {} [ 1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( -16 -16 -16 ) ( -15 -16 -16 ) ( -16 -15 -16 ) {} [ -1 0 0 0 ] [ 0 -1 0 0 ] 0 1 1
+( 16 16 16 ) ( 16 17 16 ) ( 17 16 16 ) {} [ 1 0 0 0 ] [ 0 -1 0 0 ] 0 1 1
+( 16 16 16 ) ( 17 16 16 ) ( 16 16 17 ) {} [ -1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 16 16 16 ) ( 16 16 17 ) ( 16 17 16 ) {} [ 0 1 0 0 ] [ 0 0 -1 0 ] 0 1 1
+}}"#,
+            textures[0], textures[1], textures[2], textures[3], textures[4], textures[5]
+        )
+    }
+
+    #[test]
+    fn test_convex_hull() {
+        let map = from_str(&format!("{{\n{}\n}}", cube(["A"; 6]))).unwrap();
+        let hull = map.entities[0].brushes[0].convex_hull().unwrap();
+
+        assert_eq!(hull.vertices.len(), 8);
+        for v in &hull.vertices {
+            assert!(v.iter().all(|c| c.abs() == 16.0));
+        }
+
+        assert_eq!(hull.planes.len(), 6);
+        for plane in &hull.planes {
+            assert_eq!(plane[3], 16.0);
+            for v in &hull.vertices {
+                let distance = plane[0] * v[0] + plane[1] * v[1] + plane[2] * v[2] - plane[3];
+                assert!(distance <= 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn test_collision_hulls_skip_non_solid_brushes() {
+        let map = from_str(&format!(
+            "{{\n\"classname\" \"worldspawn\"\n{}\n{}\n{}\n}}\n{{\n\"classname\" \"trigger_once\"\n{}\n{}\n{}\n}}",
+            cube(["A"; 6]),
+            cube(["*water1"; 6]),
+            cube(["skip", "skip", "A", "skip", "skip", "skip"]),
+            cube(["trigger"; 6]),
+            cube(["SKIP"; 6]),
+            cube(["clip"; 6]),
+        ))
+        .unwrap();
+        let hulls = map.collision_hulls().unwrap();
+
+        let located = hulls
+            .iter()
+            .map(|hull| (hull.entity, hull.brush))
+            .collect::<Vec<_>>();
+        assert_eq!(located, vec![(0, 0), (0, 2), (1, 2)]);
+
+        let classes = |name: &str| (name == "clip").then_some(TextureClass::Invisible);
+        let options = MeshOptions {
+            texture_classes: Some(&classes),
+            ..Default::default()
+        };
+        assert_eq!(map.collision_hulls_with(&options).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_collision_hulls_skip_degenerate_brushes() {
+        let mut map = from_str(&format!(
+            "{{\n\"classname\" \"worldspawn\"\n{}\n{}\n}}",
+            cube(["A"; 6]),
+            cube(["A"; 6]),
+        ))
+        .unwrap();
+        map.entities[0].brushes[0].faces.pop();
+
+        let hulls = map.collision_hulls().unwrap();
+        assert_eq!(hulls.len(), 1);
+        assert_eq!((hulls[0].entity, hulls[0].brush), (0, 1));
+
+        let strict = MeshOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let err = map.collision_hulls_with(&strict).unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            "entity 0: brush 0: face 1 is not bounded by the brush"
+        );
+    }
+}
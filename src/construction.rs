@@ -0,0 +1,597 @@
+use crate::geometry::face_windings;
+use crate::meshing::{Epsilons, Plane};
+use crate::{Brush, Entity, Face, Map};
+use anyhow::{anyhow, Result};
+use glam::{DVec3, Vec3};
+use std::collections::HashMap;
+
+/// Most distinct points [convex_hull] takes. The hull is found by brute force over all triples
+/// of points, which gets slow beyond this.
+pub(crate) const MAX_HULL_POINTS: usize = 128;
+
+/// A plane of a convex hull with three of the hull's points on it, spanning as large a
+/// triangle as possible.
+pub(crate) struct HullPlane {
+    pub normal: DVec3,
+    pub distance: f64,
+    pub triangle: [DVec3; 3],
+}
+
+impl Brush {
+    /// An axis aligned box between the corners `min` and `max`, which may be given in any
+    /// order. Fails if the box is flat along any axis.
+    pub fn cuboid(min: [f32; 3], max: [f32; 3], texture: &str) -> Result<Self> {
+        let (min, max) = box_corners(min, max)?;
+
+        let mut faces = Vec::new();
+        for axis in 0..3 {
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+            for (corner, sign) in [(min, -1.0), (max, 1.0)] {
+                let mut normal = DVec3::ZERO;
+                normal[axis] = sign;
+                let mut points = [corner; 3];
+                points[1][u] = min[u] + max[u] - corner[u];
+                points[2][v] = min[v] + max[v] - corner[v];
+                faces.push(face_from_points(points, normal, texture));
+            }
+        }
+        Ok(Self { faces })
+    }
+
+    /// A prism around the Z axis with `sides` sides, standing on `center` and reaching `height`
+    /// up.
+    pub fn cylinder(
+        center: [f32; 3],
+        radius: f32,
+        height: f32,
+        sides: usize,
+        texture: &str,
+    ) -> Result<Self> {
+        if sides < 3 {
+            return Err(anyhow!("a cylinder needs at least 3 sides"));
+        }
+        if radius <= 0.0 || height <= 0.0 {
+            return Err(anyhow!("a cylinder needs a positive radius and height"));
+        }
+
+        let center = Vec3::from_array(center).as_dvec3();
+        let up = DVec3::Z * height as f64;
+        let ring = (0..sides)
+            .map(|k| {
+                let angle = std::f64::consts::TAU * k as f64 / sides as f64;
+                center + DVec3::new(angle.cos(), angle.sin(), 0.0) * radius as f64
+            })
+            .collect::<Vec<_>>();
+
+        let mut faces = vec![
+            face_from_points([ring[0], ring[1], ring[2]], DVec3::NEG_Z, texture),
+            face_from_points(
+                [ring[0], ring[1], ring[2]].map(|p| p + up),
+                DVec3::Z,
+                texture,
+            ),
+        ];
+        for k in 0..sides {
+            let (a, b) = (ring[k], ring[(k + 1) % sides]);
+            let normal = ((a + b) * 0.5 - center).normalize();
+            faces.push(face_from_points([a, b, a + up], normal, texture));
+        }
+        Ok(Self { faces })
+    }
+
+    /// A ramp filling the box between `min` and `max`, rising from the bottom at `min.x` to the
+    /// top at `max.x`. Fails like [Brush::cuboid] for flat boxes.
+    pub fn wedge(min: [f32; 3], max: [f32; 3], texture: &str) -> Result<Self> {
+        let (min, max) = box_corners(min, max)?;
+        let corner = |x: f64, y: f64, z: f64| DVec3::new(x, y, z);
+
+        let slope = DVec3::new(-(max.z - min.z), 0.0, max.x - min.x).normalize();
+        let faces = vec![
+            face_from_points(
+                [
+                    min,
+                    corner(max.x, min.y, min.z),
+                    corner(min.x, max.y, min.z),
+                ],
+                DVec3::NEG_Z,
+                texture,
+            ),
+            face_from_points(
+                [
+                    corner(max.x, min.y, min.z),
+                    max,
+                    corner(max.x, min.y, max.z),
+                ],
+                DVec3::X,
+                texture,
+            ),
+            face_from_points(
+                [
+                    min,
+                    corner(max.x, min.y, min.z),
+                    corner(max.x, min.y, max.z),
+                ],
+                DVec3::NEG_Y,
+                texture,
+            ),
+            face_from_points(
+                [
+                    corner(min.x, max.y, min.z),
+                    corner(max.x, max.y, min.z),
+                    max,
+                ],
+                DVec3::Y,
+                texture,
+            ),
+            face_from_points([min, corner(min.x, max.y, min.z), max], slope, texture),
+        ];
+        Ok(Self { faces })
+    }
+
+    /// The brush enclosed by `planes`, each `[x, y, z, d]` with an outward normal and points on
+    /// it satisfying `normal · point = d`, like [crate::ConvexHull::planes]. Planes that don't
+    /// touch the volume are dropped.
+    pub fn from_planes(planes: &[[f32; 4]], texture: &str) -> Result<Self> {
+        let planes = planes
+            .iter()
+            .enumerate()
+            .map(|(i, [x, y, z, d])| {
+                let normal = Vec3::new(*x, *y, *z).as_dvec3().normalize_or_zero();
+                if normal == DVec3::ZERO {
+                    return Err(anyhow!("plane {} has no normal", i));
+                }
+                let distance = *d as f64 / Vec3::new(*x, *y, *z).length() as f64;
+                Ok(Plane::new(normal * distance, normal))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let faces = face_windings(&planes, &Epsilons::default())?
+            .into_iter()
+            .map(|(i, winding)| {
+                face_from_points(largest_triangle(&winding), planes[i].normal, texture)
+            })
+            .collect();
+        Ok(Self { faces })
+    }
+
+    /// Builds the brush enclosing the convex hull of `points`, with world aligned texture axes.
+    /// Meant for blockout shapes, more than 128 distinct points are rejected.
+    pub fn from_convex_hull(points: &[[f32; 3]], texture: &str) -> Result<Self> {
+        Self::from_convex_hull_with(points, texture, &Epsilons::default())
+    }
+
+    pub fn from_convex_hull_with(
+        points: &[[f32; 3]],
+        texture: &str,
+        epsilons: &Epsilons,
+    ) -> Result<Self> {
+        let points = points
+            .iter()
+            .map(|p| Vec3::from_array(*p).as_dvec3())
+            .collect::<Vec<_>>();
+        let planes = convex_hull(&points, epsilons)?;
+
+        Ok(Self {
+            faces: planes
+                .iter()
+                .map(|plane| face_from_points(plane.triangle, plane.normal, texture))
+                .collect(),
+        })
+    }
+}
+
+/// Assembles a [Map] from a worldspawn and further entities.
+#[derive(Debug)]
+pub struct MapBuilder {
+    map: Map,
+}
+
+impl MapBuilder {
+    /// Starts a Valve 220 map with an empty worldspawn.
+    pub fn new() -> Self {
+        Self {
+            map: Map {
+                entities: vec![entity("worldspawn", &[("mapversion", "220")], Vec::new())],
+            },
+        }
+    }
+
+    /// Sets a property of the worldspawn.
+    pub fn property(mut self, key: &str, value: &str) -> Self {
+        self.map.entities[0]
+            .properties
+            .insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// Adds a brush to the worldspawn.
+    pub fn brush(mut self, brush: Brush) -> Self {
+        self.map.entities[0].brushes.push(brush);
+        self
+    }
+
+    /// Adds an entity owning `brushes`, such as a `func_door`.
+    pub fn brush_entity(
+        self,
+        classname: &str,
+        properties: &[(&str, &str)],
+        brushes: Vec<Brush>,
+    ) -> Self {
+        self.entity(entity(classname, properties, brushes))
+    }
+
+    /// Adds an entity without brushes at `origin`.
+    pub fn point_entity(
+        self,
+        classname: &str,
+        origin: [f32; 3],
+        properties: &[(&str, &str)],
+    ) -> Self {
+        let mut entity = entity(classname, properties, Vec::new());
+        let [x, y, z] = origin;
+        entity
+            .properties
+            .insert("origin".to_string(), format!("{} {} {}", x, y, z));
+        self.entity(entity)
+    }
+
+    /// Adds an already assembled entity.
+    pub fn entity(mut self, entity: Entity) -> Self {
+        self.map.entities.push(entity);
+        self
+    }
+
+    /// Returns the assembled map, the worldspawn first and the other entities in the order they
+    /// were added.
+    pub fn build(self) -> Map {
+        self.map
+    }
+}
+
+impl Default for MapBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn entity(classname: &str, properties: &[(&str, &str)], brushes: Vec<Brush>) -> Entity {
+    let mut all = HashMap::new();
+    all.insert("classname".to_string(), classname.to_string());
+    for (key, value) in properties {
+        all.insert(key.to_string(), value.to_string());
+    }
+    Entity {
+        properties: all,
+        brushes,
+    }
+}
+
+/// Sorts the corners of a box given in any order, failing if it has no volume.
+fn box_corners(a: [f32; 3], b: [f32; 3]) -> Result<(DVec3, DVec3)> {
+    let (a, b) = (Vec3::from_array(a), Vec3::from_array(b));
+    let (min, max) = (a.min(b), a.max(b));
+    if !(max - min).cmpgt(Vec3::ZERO).all() {
+        return Err(anyhow!("the box has no volume between {} and {}", min, max));
+    }
+    Ok((min.as_dvec3(), max.as_dvec3()))
+}
+
+/// Finds the planes of the convex hull of `points`, facing outwards. Fails for more than
+/// [MAX_HULL_POINTS] distinct points.
+pub(crate) fn convex_hull(points: &[DVec3], epsilons: &Epsilons) -> Result<Vec<HullPlane>> {
+    let epsilon = epsilons.vertex as f64;
+    let mut unique: Vec<DVec3> = Vec::new();
+    for point in points {
+        if unique.iter().all(|p| p.distance(*point) > epsilon) {
+            unique.push(*point);
+        }
+    }
+    if unique.len() < 4 {
+        return Err(anyhow!("a convex hull needs at least 4 distinct points"));
+    }
+    if unique.len() > MAX_HULL_POINTS {
+        return Err(anyhow!(
+            "a convex hull takes at most {} distinct points, got {}",
+            MAX_HULL_POINTS,
+            unique.len()
+        ));
+    }
+
+    let mut planes: Vec<HullPlane> = Vec::new();
+    for i in 0..unique.len() {
+        for j in i + 1..unique.len() {
+            for k in j + 1..unique.len() {
+                let (a, b, c) = (unique[i], unique[j], unique[k]);
+                let normal = (b - a).cross(c - a);
+                if normal.length() <= epsilon * epsilon {
+                    continue;
+                }
+                let mut normal = normal.normalize();
+
+                let distances = unique.iter().map(|p| normal.dot(*p - a));
+                if distances.clone().all(|d| d >= -epsilon) {
+                    normal = -normal;
+                } else if !distances.clone().all(|d| d <= epsilon) {
+                    continue;
+                }
+
+                let distance = normal.dot(a);
+                let known = planes.iter().any(|plane| {
+                    plane.normal.dot(normal) > 1.0 - 1e-9
+                        && (plane.distance - distance).abs() <= epsilon
+                });
+                if !known {
+                    let on_plane = unique
+                        .iter()
+                        .copied()
+                        .filter(|p| (normal.dot(*p) - distance).abs() <= epsilon)
+                        .collect::<Vec<_>>();
+                    planes.push(HullPlane {
+                        normal,
+                        distance,
+                        triangle: largest_triangle(&on_plane),
+                    });
+                }
+            }
+        }
+    }
+
+    if planes.len() < 4 {
+        return Err(anyhow!("the points don't enclose a volume"));
+    }
+    Ok(planes)
+}
+
+/// Picks three of the coplanar `points` that span a large triangle, so the plane they define is
+/// as precise as possible.
+fn largest_triangle(points: &[DVec3]) -> [DVec3; 3] {
+    let a = points[0];
+    let farthest = |from: &dyn Fn(DVec3) -> f64| {
+        points
+            .iter()
+            .copied()
+            .max_by(|p, q| from(*p).total_cmp(&from(*q)))
+            .unwrap()
+    };
+    let b = farthest(&|p| p.distance_squared(a));
+    let c = farthest(&|p| (p - a).cross(b - a).length_squared());
+    [a, b, c]
+}
+
+/// Builds a face on the plane through `points`, winding them so the face points along `normal`,
+/// with world aligned texture axes.
+pub(crate) fn face_from_points(points: [DVec3; 3], normal: DVec3, texture: &str) -> Face {
+    let [a, mut b, mut c] = points;
+    // faces point along (c - a) x (b - a)
+    if (c - a).cross(b - a).dot(normal) < 0.0 {
+        std::mem::swap(&mut b, &mut c);
+    }
+
+    let (axis_u, axis_v) = paraxial_axes(normal.as_vec3());
+    Face {
+        triangle: [a, b, c].map(|p| p.as_vec3().to_array()),
+        texture_name: texture.to_string(),
+        axis_u: axis_u.to_array(),
+        axis_v: axis_v.to_array(),
+        offset: [0.0, 0.0],
+        rotation: 0.0,
+        scale: [1.0, 1.0],
+    }
+}
+
+/// World aligned texture axes for a face, projecting along the axis closest to its normal like
+/// TrenchBroom does for new Valve 220 faces.
+pub(crate) fn paraxial_axes(normal: Vec3) -> (Vec3, Vec3) {
+    let abs = normal.abs();
+    if abs.z >= abs.x && abs.z >= abs.y {
+        (Vec3::X, Vec3::NEG_Y)
+    } else if abs.x >= abs.y {
+        (Vec3::Y, Vec3::NEG_Z)
+    } else {
+        (Vec3::X, Vec3::NEG_Z)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::meshing::ToMesh;
+
+    const CORNERS: [[f32; 3]; 8] = [
+        [-16.0, -16.0, -16.0],
+        [16.0, -16.0, -16.0],
+        [-16.0, 16.0, -16.0],
+        [16.0, 16.0, -16.0],
+        [-16.0, -16.0, 16.0],
+        [16.0, -16.0, 16.0],
+        [-16.0, 16.0, 16.0],
+        [16.0, 16.0, 16.0],
+    ];
+
+    fn corners(brush: &Brush) -> Vec<[f32; 3]> {
+        let mut corners = brush.convex_hull().unwrap().vertices;
+        corners.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        corners
+    }
+
+    #[test]
+    fn test_cuboid() {
+        let brush = Brush::cuboid([16.0, 16.0, 16.0], [-16.0, -16.0, -16.0], "BOX").unwrap();
+        assert_eq!(brush.faces.len(), 6);
+        assert!(brush.validate().is_empty());
+
+        let flat = Brush::cuboid([0.0; 3], [16.0, 16.0, 0.0], "BOX").unwrap_err();
+        assert_eq!(
+            flat.to_string(),
+            "the box has no volume between [0, 0, 0] and [16, 16, 0]"
+        );
+        assert!(Brush::wedge([0.0; 3], [0.0, 16.0, 16.0], "RAMP").is_err());
+
+        let mut expected = CORNERS.to_vec();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(corners(&brush), expected);
+
+        // the corners are used as they are, so they stay exact in the map file
+        for face in &brush.faces {
+            assert!(face.triangle.iter().flatten().all(|c| c.abs() == 16.0));
+        }
+    }
+
+    #[test]
+    fn test_cylinder() {
+        let brush = Brush::cylinder([0.0, 0.0, 0.0], 32.0, 64.0, 8, "PIPE").unwrap();
+        assert_eq!(brush.faces.len(), 10);
+        assert!(brush.validate().is_empty());
+
+        let corners = corners(&brush);
+        assert_eq!(corners.len(), 16);
+        for [x, y, z] in corners {
+            assert!((Vec3::new(x, y, 0.0).length() - 32.0).abs() < 1e-3);
+            assert!(z == 0.0 || z == 64.0);
+        }
+
+        assert!(Brush::cylinder([0.0; 3], 32.0, 64.0, 2, "PIPE").is_err());
+    }
+
+    #[test]
+    fn test_wedge() {
+        let brush = Brush::wedge([0.0, 0.0, 0.0], [64.0, 32.0, 32.0], "RAMP").unwrap();
+        assert_eq!(brush.faces.len(), 5);
+        assert!(brush.validate().is_empty());
+        assert_eq!(
+            corners(&brush),
+            vec![
+                [0.0, 0.0, 0.0],
+                [0.0, 32.0, 0.0],
+                [64.0, 0.0, 0.0],
+                [64.0, 0.0, 32.0],
+                [64.0, 32.0, 0.0],
+                [64.0, 32.0, 32.0],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_from_planes() {
+        let hull = Brush::cuboid([-16.0; 3], [16.0; 3], "BOX")
+            .unwrap()
+            .convex_hull()
+            .unwrap();
+        let mut planes = hull.planes.clone();
+        // a plane beyond the box doesn't add a face
+        planes.push([1.0, 1.0, 1.0, 1000.0]);
+
+        let brush = Brush::from_planes(&planes, "BOX").unwrap();
+        assert_eq!(brush.faces.len(), 6);
+        assert_eq!(
+            corners(&brush),
+            corners(&Brush::cuboid([-16.0; 3], [16.0; 3], "BOX").unwrap())
+        );
+
+        let error = Brush::from_planes(&planes[1..], "BOX").unwrap_err();
+        assert!(error.to_string().ends_with("is not bounded by the brush"));
+    }
+
+    #[test]
+    fn test_map_builder() {
+        let map = MapBuilder::new()
+            .property("message", "test")
+            .brush(Brush::cuboid([-64.0, -64.0, -16.0], [64.0, 64.0, 0.0], "FLOOR").unwrap())
+            .brush_entity(
+                "func_door",
+                &[("speed", "100")],
+                vec![Brush::cuboid([0.0; 3], [8.0, 64.0, 96.0], "DOOR").unwrap()],
+            )
+            .point_entity("info_player_start", [0.0, 0.0, 24.0], &[])
+            .build();
+
+        assert_eq!(map.entities.len(), 3);
+        assert_eq!(map.entities[0].properties["classname"], "worldspawn");
+        assert_eq!(map.entities[0].properties["mapversion"], "220");
+        assert_eq!(map.entities[0].properties["message"], "test");
+        assert_eq!(map.entities[1].properties["speed"], "100");
+        assert_eq!(map.entities[2].properties["origin"], "0 0 24");
+        assert!(map.validate().is_empty());
+        assert!(map.entities[1].to_mesh().is_ok());
+    }
+
+    #[test]
+    fn test_from_convex_hull() {
+        // points inside and on the edges don't add faces
+        let mut points = CORNERS.to_vec();
+        points.push([0.0, 0.0, 0.0]);
+        points.push([16.0, 0.0, 16.0]);
+        let brush = Brush::from_convex_hull(&points, "BOX").unwrap();
+        assert_eq!(brush.faces.len(), 6);
+        assert!(brush.validate().is_empty());
+
+        let mesh = brush.to_mesh().unwrap();
+        assert_eq!(mesh.positions.len(), 24);
+        for corner in CORNERS {
+            assert!(mesh.positions.contains(&corner));
+        }
+
+        let top = brush
+            .faces
+            .iter()
+            .find(|face| face.triangle.iter().all(|p| p[2] == 16.0))
+            .unwrap();
+        assert_eq!(
+            (top.axis_u, top.axis_v),
+            ([1.0, 0.0, 0.0], [0.0, -1.0, 0.0])
+        );
+    }
+
+    #[test]
+    fn test_from_convex_hull_pyramid() {
+        let points = [
+            [0.0, 0.0, 0.0],
+            [64.0, 0.0, 0.0],
+            [0.0, 64.0, 0.0],
+            [64.0, 64.0, 0.0],
+            [32.0, 32.0, 48.0],
+        ];
+        let brush = Brush::from_convex_hull(&points, "ROOF").unwrap();
+        assert_eq!(brush.faces.len(), 5);
+        assert!(brush.validate().is_empty());
+    }
+
+    #[test]
+    fn test_from_convex_hull_flat() {
+        let points = &CORNERS[..4];
+        let error = Brush::from_convex_hull(points, "BOX").unwrap_err();
+        assert_eq!(error.to_string(), "the points don't enclose a volume");
+    }
+
+    #[test]
+    fn test_from_convex_hull_too_many_points() {
+        let points = (0..200)
+            .map(|i| {
+                let angle = i as f32 * 0.1;
+                [angle.cos() * 64.0, angle.sin() * 64.0, i as f32]
+            })
+            .collect::<Vec<_>>();
+        let error = Brush::from_convex_hull(&points, "BOX").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "a convex hull takes at most 128 distinct points, got 200"
+        );
+
+        // a coarser vertex epsilon merges the points into fewer
+        let epsilons = Epsilons {
+            plane: 0.001,
+            vertex: 0.5,
+        };
+        let points = CORNERS
+            .iter()
+            .flat_map(|[x, y, z]| [[*x, *y, *z], [*x + 0.25, *y, *z]])
+            .collect::<Vec<_>>();
+        let max_x = |brush: &Brush| corners(brush).iter().map(|c| c[0]).fold(f32::MIN, f32::max);
+        assert_eq!(
+            max_x(&Brush::from_convex_hull(&points, "BOX").unwrap()),
+            16.25
+        );
+        let brush = Brush::from_convex_hull_with(&points, "BOX", &epsilons).unwrap();
+        assert_eq!(max_x(&brush), 16.0);
+    }
+}
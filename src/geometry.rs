@@ -0,0 +1,310 @@
+use crate::meshing::{face_winding, is_bounded, Epsilons, Plane};
+use crate::{Brush, Entity, Map};
+use anyhow::{anyhow, Context, Result};
+use glam::{DVec3, Vec3};
+
+/// An axis aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+}
+
+impl Aabb {
+    pub fn center(&self) -> [f32; 3] {
+        ((Vec3::from_array(self.min) + Vec3::from_array(self.max)) * 0.5).to_array()
+    }
+
+    pub fn size(&self) -> [f32; 3] {
+        (Vec3::from_array(self.max) - Vec3::from_array(self.min)).to_array()
+    }
+
+    /// The smallest box containing both boxes.
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: Vec3::from_array(self.min)
+                .min(Vec3::from_array(other.min))
+                .to_array(),
+            max: Vec3::from_array(self.max)
+                .max(Vec3::from_array(other.max))
+                .to_array(),
+        }
+    }
+
+    /// Whether `point` is inside or on the box.
+    pub fn contains_point(&self, point: [f32; 3]) -> bool {
+        let point = Vec3::from_array(point);
+        point.cmpge(Vec3::from_array(self.min)).all()
+            && point.cmple(Vec3::from_array(self.max)).all()
+    }
+
+    /// Distance from `point` to the nearest point of the box, 0 if it's inside.
+    pub fn distance_to_point(&self, point: [f32; 3]) -> f32 {
+        let point = Vec3::from_array(point);
+        let outside = (Vec3::from_array(self.min) - point)
+            .max(point - Vec3::from_array(self.max))
+            .max(Vec3::ZERO);
+        outside.length()
+    }
+
+    /// Whether the boxes overlap or touch.
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        Vec3::from_array(self.min)
+            .cmple(Vec3::from_array(other.max))
+            .all()
+            && Vec3::from_array(other.min)
+                .cmple(Vec3::from_array(self.max))
+                .all()
+    }
+}
+
+impl Brush {
+    /// The bounds of the corners of the brush.
+    pub fn bounds(&self) -> Result<Aabb> {
+        let points = windings(&self.planes()?)?.into_iter().flatten();
+        let (min, max) = points.fold(
+            (DVec3::splat(f64::MAX), DVec3::splat(f64::MIN)),
+            |(min, max), p| (min.min(p), max.max(p)),
+        );
+        Ok(Aabb {
+            min: min.as_vec3().to_array(),
+            max: max.as_vec3().to_array(),
+        })
+    }
+
+    pub fn volume(&self) -> Result<f32> {
+        Ok(volume_and_centroid(&windings(&self.planes()?)?).0 as f32)
+    }
+
+    /// The center of mass of the brush.
+    pub fn centroid(&self) -> Result<[f32; 3]> {
+        let (_, centroid) = volume_and_centroid(&windings(&self.planes()?)?);
+        Ok(centroid.as_vec3().to_array())
+    }
+
+    /// Whether `point` is inside the brush or on its surface. Brushes that don't enclose a
+    /// volume contain nothing.
+    pub fn contains_point(&self, point: [f32; 3]) -> bool {
+        let point = Vec3::from_array(point).as_dvec3();
+        let epsilon = Epsilons::default().plane as f64;
+        self.closed_planes()
+            .is_some_and(|planes| planes.iter().all(|plane| plane.distance(point) <= epsilon))
+    }
+
+    /// Whether the brushes overlap. Brushes that only touch don't intersect, neither do brushes
+    /// that don't enclose a volume.
+    pub fn intersects(&self, other: &Brush) -> bool {
+        let (mut planes, other) = match (self.closed_planes(), other.closed_planes()) {
+            (Some(planes), Some(other)) => (planes, other),
+            _ => return false,
+        };
+        planes.extend(other);
+
+        let epsilons = Epsilons::default();
+        let windings = (0..planes.len())
+            .filter_map(|i| face_winding(&planes, i, &epsilons))
+            .collect::<Vec<_>>();
+        let (volume, _) = volume_and_centroid(&windings);
+        volume > (epsilons.vertex as f64).powi(3)
+    }
+
+    /// The planes of the brush, `None` if they don't enclose a volume.
+    pub(crate) fn closed_planes(&self) -> Option<Vec<Plane>> {
+        let planes = self.planes().ok()?;
+        windings(&planes).ok()?;
+        Some(planes)
+    }
+
+    pub(crate) fn planes(&self) -> Result<Vec<Plane>> {
+        self.faces
+            .iter()
+            .enumerate()
+            .map(|(i, face)| {
+                Plane::from_triangle(face.triangle)
+                    .ok_or_else(|| anyhow!("face {} has a degenerate plane", i))
+            })
+            .collect()
+    }
+}
+
+impl Entity {
+    /// The bounds of all brushes, `None` for point entities.
+    pub fn bounds(&self) -> Result<Option<Aabb>> {
+        let mut bounds: Option<Aabb> = None;
+        for (i, brush) in self.brushes.iter().enumerate() {
+            let brush_bounds = brush.bounds().with_context(|| format!("brush {}", i))?;
+            bounds = Some(bounds.map_or(brush_bounds, |b| b.union(&brush_bounds)));
+        }
+        Ok(bounds)
+    }
+}
+
+impl Map {
+    /// The bounds of all brushes in the map, `None` if there are none.
+    pub fn bounds(&self) -> Result<Option<Aabb>> {
+        let mut bounds: Option<Aabb> = None;
+        for (i, entity) in self.entities.iter().enumerate() {
+            let entity_bounds = entity.bounds().with_context(|| format!("entity {}", i))?;
+            if let Some(entity_bounds) = entity_bounds {
+                bounds = Some(bounds.map_or(entity_bounds, |b| b.union(&entity_bounds)));
+            }
+        }
+        Ok(bounds)
+    }
+}
+
+/// The polygons of the faces of a closed brush.
+pub(crate) fn windings(planes: &[Plane]) -> Result<Vec<Vec<DVec3>>> {
+    let windings = face_windings(planes, &Epsilons::default())?;
+    Ok(windings.into_iter().map(|(_, winding)| winding).collect())
+}
+
+/// The polygons of the faces of a closed brush with the indices of their planes. Redundant faces
+/// are left out.
+pub(crate) fn face_windings(
+    planes: &[Plane],
+    epsilons: &Epsilons,
+) -> Result<Vec<(usize, Vec<DVec3>)>> {
+    let mut windings = Vec::new();
+    for i in 0..planes.len() {
+        if let Some(winding) = face_winding(planes, i, epsilons) {
+            if !is_bounded(&winding) {
+                return Err(anyhow!("face {} is not bounded by the brush", i));
+            }
+            windings.push((i, winding));
+        }
+    }
+
+    if windings.len() < 4 {
+        return Err(anyhow!("brush does not enclose a volume"));
+    }
+    Ok(windings)
+}
+
+/// Splits the polyhedron into tetrahedra from a point inside it to the triangles of its faces.
+pub(crate) fn volume_and_centroid(windings: &[Vec<DVec3>]) -> (f64, DVec3) {
+    let count = windings.iter().map(Vec::len).sum::<usize>();
+    if count == 0 {
+        return (0.0, DVec3::ZERO);
+    }
+    let inside = windings.iter().flatten().sum::<DVec3>() / count as f64;
+
+    let mut volume = 0.0;
+    let mut moment = DVec3::ZERO;
+    for winding in windings {
+        for n in 1..winding.len() - 1 {
+            let [a, b, c] = [winding[0], winding[n], winding[n + 1]];
+            // windings run counter-clockwise around the outward normal
+            let tetrahedron = (a - inside).dot((b - inside).cross(c - inside)) / 6.0;
+            volume += tetrahedron;
+            moment += (inside + a + b + c) * 0.25 * tetrahedron;
+        }
+    }
+
+    if volume <= 0.0 {
+        return (0.0, inside);
+    }
+    (volume, moment / volume)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MapBuilder;
+
+    #[test]
+    fn test_brush_queries() {
+        let brush = Brush::cuboid([0.0, 0.0, 0.0], [64.0, 32.0, 16.0], "BOX").unwrap();
+        assert_eq!(
+            brush.bounds().unwrap(),
+            Aabb {
+                min: [0.0, 0.0, 0.0],
+                max: [64.0, 32.0, 16.0],
+            }
+        );
+        assert_eq!(brush.volume().unwrap(), 32768.0);
+        assert_eq!(brush.centroid().unwrap(), [32.0, 16.0, 8.0]);
+
+        let wedge = Brush::wedge([0.0, 0.0, 0.0], [64.0, 32.0, 16.0], "RAMP").unwrap();
+        assert_eq!(wedge.volume().unwrap(), 16384.0);
+        let [x, y, z] = wedge.centroid().unwrap();
+        assert!((x - 128.0 / 3.0).abs() < 1e-3);
+        assert!((y - 16.0).abs() < 1e-3);
+        assert!((z - 16.0 / 3.0).abs() < 1e-3);
+
+        assert!(Brush::default().bounds().is_err());
+
+        let bounds = brush.bounds().unwrap();
+        assert_eq!(bounds.distance_to_point([32.0, 16.0, 8.0]), 0.0);
+        assert_eq!(bounds.distance_to_point([-3.0, 36.0, 8.0]), 5.0);
+    }
+
+    #[test]
+    fn test_contains_point() {
+        let wedge = Brush::wedge([0.0, 0.0, 0.0], [64.0, 32.0, 16.0], "RAMP").unwrap();
+        assert!(wedge.contains_point([60.0, 16.0, 4.0]));
+        assert!(wedge.contains_point([64.0, 32.0, 16.0]));
+        assert!(!wedge.contains_point([4.0, 16.0, 12.0]));
+        assert!(!wedge.contains_point([32.0, 40.0, 1.0]));
+
+        // an empty brush has no planes to be outside of, an open one is unbounded
+        let mut open = Brush::cuboid([0.0; 3], [16.0; 3], "BOX").unwrap();
+        open.faces.pop();
+        assert!(!Brush::default().contains_point([0.0; 3]));
+        assert!(!open.contains_point([8.0; 3]));
+    }
+
+    #[test]
+    fn test_intersects() {
+        let a = Brush::cuboid([0.0; 3], [16.0; 3], "A").unwrap();
+        let touching = Brush::cuboid([16.0, 0.0, 0.0], [32.0, 16.0, 16.0], "B").unwrap();
+        let overlapping = Brush::cuboid([8.0; 3], [24.0; 3], "C").unwrap();
+        let apart = Brush::cuboid([20.0; 3], [30.0; 3], "D").unwrap();
+
+        assert!(a.intersects(&overlapping));
+        assert!(overlapping.intersects(&a));
+        assert!(!a.intersects(&touching));
+        assert!(!a.intersects(&apart));
+
+        // the boxes of the wedge and the cube overlap, the wedge itself doesn't
+        let wedge = Brush::wedge([0.0, 0.0, 0.0], [64.0, 16.0, 64.0], "RAMP").unwrap();
+        let above = Brush::cuboid([0.0, 0.0, 40.0], [16.0, 16.0, 56.0], "E").unwrap();
+        assert!(wedge.bounds().unwrap().intersects(&above.bounds().unwrap()));
+        assert!(!wedge.intersects(&above));
+
+        assert!(!a.intersects(&Brush::default()));
+        assert!(!Brush::default().intersects(&a));
+    }
+
+    #[test]
+    fn test_entity_and_map_bounds() {
+        let map = MapBuilder::new()
+            .brush(Brush::cuboid([-64.0, -64.0, -16.0], [64.0, 64.0, 0.0], "FLOOR").unwrap())
+            .brush_entity(
+                "trigger_once",
+                &[],
+                vec![
+                    Brush::cuboid([0.0; 3], [16.0; 3], "TRIGGER").unwrap(),
+                    Brush::cuboid([16.0, 0.0, 0.0], [32.0, 8.0, 128.0], "TRIGGER").unwrap(),
+                ],
+            )
+            .point_entity("info_player_start", [0.0, 0.0, 24.0], &[])
+            .build();
+
+        assert_eq!(
+            map.entities[1].bounds().unwrap(),
+            Some(Aabb {
+                min: [0.0, 0.0, 0.0],
+                max: [32.0, 16.0, 128.0],
+            })
+        );
+        assert_eq!(map.entities[2].bounds().unwrap(), None);
+        assert_eq!(
+            map.bounds().unwrap(),
+            Some(Aabb {
+                min: [-64.0, -64.0, -16.0],
+                max: [64.0, 64.0, 128.0],
+            })
+        );
+    }
+}
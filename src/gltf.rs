@@ -0,0 +1,429 @@
+//! Exports a [Map] as a glTF 2.0 scene, either as a binary `.glb` or as `.gltf` JSON with the
+//! buffer embedded.
+
+use crate::meshing::{CoordinateSystem, MeshOptions};
+use crate::{Map, Mesh};
+use anyhow::{Context, Result};
+use serde_json::{json, Map as JsonMap, Value};
+use std::collections::HashMap;
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+
+const GLB_MAGIC: u32 = 0x4654_6c67;
+const GLB_JSON_CHUNK: u32 = 0x4e4f_534a;
+const GLB_BIN_CHUNK: u32 = 0x004e_4942;
+
+/// Options for [Map::to_glb_with] and [Map::to_gltf_with].
+#[derive(Clone, Copy)]
+pub struct GltfOptions<'a> {
+    /// How brushes are meshed. glTF is Y-up and in meters, which the default converts to at
+    /// 32 units per meter.
+    pub mesh: MeshOptions<'a>,
+    /// Extension appended to texture names for the image URIs of the materials.
+    pub texture_extension: &'a str,
+}
+
+impl Default for GltfOptions<'_> {
+    fn default() -> Self {
+        Self {
+            mesh: MeshOptions {
+                coordinates: CoordinateSystem {
+                    scale: 1.0 / 32.0,
+                    ..CoordinateSystem::Y_UP
+                },
+                ..Default::default()
+            },
+            texture_extension: "png",
+        }
+    }
+}
+
+impl Map {
+    /// Exports the map as a binary glTF. Every entity becomes a node carrying its properties in
+    /// `extras`. Brush entities are meshed around their pivot with one primitive per texture,
+    /// point entities are empty nodes at their `origin`.
+    pub fn to_glb(&self) -> Result<Vec<u8>> {
+        self.to_glb_with(&GltfOptions::default())
+    }
+
+    pub fn to_glb_with(&self, options: &GltfOptions) -> Result<Vec<u8>> {
+        let (mut document, buffer) = export(self, options)?;
+        // glTF doesn't allow empty buffers, maps without brushes have none
+        if !buffer.is_empty() {
+            document["buffers"] = json!([{ "byteLength": buffer.len() }]);
+        }
+
+        let mut json = serde_json::to_vec(&document)?;
+        pad(&mut json, b' ');
+        let mut bin = buffer;
+        pad(&mut bin, 0);
+
+        let bin_length = if bin.is_empty() { 0 } else { 8 + bin.len() };
+        let length = 12 + 8 + json.len() + bin_length;
+        let mut glb = Vec::with_capacity(length);
+        for word in [
+            GLB_MAGIC,
+            2,
+            length as u32,
+            json.len() as u32,
+            GLB_JSON_CHUNK,
+        ] {
+            glb.extend_from_slice(&word.to_le_bytes());
+        }
+        glb.append(&mut json);
+        if !bin.is_empty() {
+            for word in [bin.len() as u32, GLB_BIN_CHUNK] {
+                glb.extend_from_slice(&word.to_le_bytes());
+            }
+            glb.append(&mut bin);
+        }
+        Ok(glb)
+    }
+
+    /// Exports the map as glTF JSON with the buffer embedded as a data URI, see [Map::to_glb].
+    pub fn to_gltf(&self) -> Result<String> {
+        self.to_gltf_with(&GltfOptions::default())
+    }
+
+    pub fn to_gltf_with(&self, options: &GltfOptions) -> Result<String> {
+        let (mut document, buffer) = export(self, options)?;
+        if !buffer.is_empty() {
+            document["buffers"] = json!([{
+                "byteLength": buffer.len(),
+                "uri": format!("data:application/octet-stream;base64,{}", base64(&buffer)),
+            }]);
+        }
+        Ok(serde_json::to_string_pretty(&document)?)
+    }
+}
+
+/// Builds the glTF document without its `buffers`, and the contents of its one buffer.
+fn export(map: &Map, options: &GltfOptions) -> Result<(Value, Vec<u8>)> {
+    let mut builder = Builder::new(options);
+    let mut nodes = Vec::new();
+
+    for (i, entity) in map.entities.iter().enumerate() {
+        let mut node = JsonMap::new();
+        let name = entity
+            .properties
+            .get("targetname")
+            .or_else(|| entity.properties.get("classname"));
+        if let Some(name) = name {
+            node.insert("name".into(), json!(name));
+        }
+
+        let translation = if entity.brushes.is_empty() {
+            entity
+                .vector("origin")
+                .map(|origin| options.mesh.coordinates.point(origin))
+        } else {
+            let (meshes, pivot) =
+                Mesh::from_entity_by_texture_around_pivot_with(entity, &options.mesh)
+                    .with_context(|| format!("entity {}", i))?;
+            if let Some(mesh) = builder.mesh(name, &meshes) {
+                node.insert("mesh".into(), json!(mesh));
+            }
+            Some(pivot)
+        };
+        if let Some(translation) = translation.filter(|t| *t != [0.0; 3]) {
+            node.insert("translation".into(), json!(translation));
+        }
+
+        node.insert("extras".into(), json!(entity.properties));
+        nodes.push(Value::Object(node));
+    }
+
+    let scene_nodes = (0..nodes.len()).collect::<Vec<_>>();
+    let mut document = json!({
+        "asset": { "version": "2.0", "generator": "valve-map" },
+        "scene": 0,
+        "scenes": [{ "nodes": scene_nodes }],
+        "nodes": nodes,
+    });
+    for (key, values) in [
+        ("meshes", builder.meshes),
+        ("materials", builder.materials),
+        ("textures", builder.textures),
+        ("images", builder.images),
+        ("accessors", builder.accessors),
+        ("bufferViews", builder.buffer_views),
+    ] {
+        if !values.is_empty() {
+            document[key] = Value::Array(values);
+        }
+    }
+
+    Ok((document, builder.buffer))
+}
+
+struct Builder<'a> {
+    options: &'a GltfOptions<'a>,
+    buffer: Vec<u8>,
+    buffer_views: Vec<Value>,
+    accessors: Vec<Value>,
+    meshes: Vec<Value>,
+    materials: Vec<Value>,
+    textures: Vec<Value>,
+    images: Vec<Value>,
+    material_indices: HashMap<String, usize>,
+}
+
+impl<'a> Builder<'a> {
+    fn new(options: &'a GltfOptions<'a>) -> Self {
+        Self {
+            options,
+            buffer: Vec::new(),
+            buffer_views: Vec::new(),
+            accessors: Vec::new(),
+            meshes: Vec::new(),
+            materials: Vec::new(),
+            textures: Vec::new(),
+            images: Vec::new(),
+            material_indices: HashMap::new(),
+        }
+    }
+
+    /// Adds a mesh with one primitive per texture, `None` if there's nothing to draw.
+    fn mesh(&mut self, name: Option<&String>, meshes: &HashMap<String, Mesh>) -> Option<usize> {
+        let mut textures = meshes
+            .iter()
+            .filter(|(_, mesh)| !mesh.indices.is_empty())
+            .collect::<Vec<_>>();
+        if textures.is_empty() {
+            return None;
+        }
+        textures.sort_by_key(|(texture, _)| *texture);
+
+        let primitives = textures
+            .into_iter()
+            .map(|(texture, mesh)| {
+                let mut attributes = JsonMap::new();
+                attributes.insert("POSITION".into(), json!(self.floats(&mesh.positions)));
+                attributes.insert("NORMAL".into(), json!(self.floats(&mesh.normals)));
+                attributes.insert("TEXCOORD_0".into(), json!(self.floats(&mesh.uvs)));
+                attributes.insert("TANGENT".into(), json!(self.floats(&mesh.tangents)));
+                if let Some(uvs) = &mesh.uvs_lightmap {
+                    attributes.insert("TEXCOORD_1".into(), json!(self.floats(uvs)));
+                }
+                if let Some(colors) = &mesh.colors {
+                    attributes.insert("COLOR_0".into(), json!(self.floats(colors)));
+                }
+
+                json!({
+                    "attributes": attributes,
+                    "indices": self.indices(&mesh.indices),
+                    "material": self.material(texture),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut mesh = json!({ "primitives": primitives });
+        if let Some(name) = name {
+            mesh["name"] = json!(name);
+        }
+        self.meshes.push(mesh);
+        Some(self.meshes.len() - 1)
+    }
+
+    fn material(&mut self, texture: &str) -> usize {
+        if let Some(index) = self.material_indices.get(texture) {
+            return *index;
+        }
+
+        self.images.push(json!({
+            "uri": format!("{}.{}", texture, self.options.texture_extension),
+        }));
+        self.textures
+            .push(json!({ "source": self.images.len() - 1 }));
+        self.materials.push(json!({
+            "name": texture,
+            "pbrMetallicRoughness": {
+                "baseColorTexture": { "index": self.textures.len() - 1 },
+                "metallicFactor": 0.0,
+                "roughnessFactor": 1.0,
+            },
+        }));
+
+        let index = self.materials.len() - 1;
+        self.material_indices.insert(texture.to_string(), index);
+        index
+    }
+
+    fn floats<const N: usize>(&mut self, data: &[[f32; N]]) -> usize {
+        let mut min = [f32::MAX; N];
+        let mut max = [f32::MIN; N];
+        for element in data {
+            for n in 0..N {
+                min[n] = min[n].min(element[n]);
+                max[n] = max[n].max(element[n]);
+            }
+        }
+
+        let bytes = data
+            .iter()
+            .flatten()
+            .flat_map(|f| f.to_le_bytes())
+            .collect::<Vec<_>>();
+        let view = self.view(&bytes, ARRAY_BUFFER);
+        self.accessors.push(json!({
+            "bufferView": view,
+            "componentType": FLOAT,
+            "count": data.len(),
+            "type": format!("VEC{}", N),
+            "min": min.to_vec(),
+            "max": max.to_vec(),
+        }));
+        self.accessors.len() - 1
+    }
+
+    fn indices(&mut self, indices: &[u32]) -> usize {
+        let bytes = indices
+            .iter()
+            .flat_map(|i| i.to_le_bytes())
+            .collect::<Vec<_>>();
+        let view = self.view(&bytes, ELEMENT_ARRAY_BUFFER);
+        self.accessors.push(json!({
+            "bufferView": view,
+            "componentType": UNSIGNED_INT,
+            "count": indices.len(),
+            "type": "SCALAR",
+        }));
+        self.accessors.len() - 1
+    }
+
+    fn view(&mut self, bytes: &[u8], target: u32) -> usize {
+        // every accessor holds 4 byte components, so views stay aligned without padding
+        self.buffer_views.push(json!({
+            "buffer": 0,
+            "byteOffset": self.buffer.len(),
+            "byteLength": bytes.len(),
+            "target": target,
+        }));
+        self.buffer.extend_from_slice(bytes);
+        self.buffer_views.len() - 1
+    }
+}
+
+/// Pads GLB chunks to a multiple of 4 bytes.
+fn pad(bytes: &mut Vec<u8>, with: u8) {
+    while !bytes.len().is_multiple_of(4) {
+        bytes.push(with);
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_str;
+
+    #[test]
+    fn test_to_glb() {
+        let map = from_str(include_str!("../examples/basic.map")).unwrap();
+        let glb = map.to_glb().unwrap();
+
+        let word = |i: usize| u32::from_le_bytes(glb[i * 4..i * 4 + 4].try_into().unwrap());
+        assert_eq!(word(0), GLB_MAGIC);
+        assert_eq!(word(1), 2);
+        assert_eq!(word(2) as usize, glb.len());
+        assert_eq!(word(4), GLB_JSON_CHUNK);
+
+        let json_length = word(3) as usize;
+        let document: Value = serde_json::from_slice(&glb[20..20 + json_length]).unwrap();
+        let bin = &glb[20 + json_length + 8..];
+        assert_eq!(document["buffers"][0]["byteLength"], bin.len());
+
+        let nodes = document["nodes"].as_array().unwrap();
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(nodes[0]["name"], "worldspawn");
+        assert_eq!(nodes[0]["mesh"], 0);
+        assert_eq!(nodes[0]["extras"]["mapversion"], "220");
+
+        // the player start is at 0 0 92, converted to Y-up meters
+        assert_eq!(nodes[1]["name"], "info_player_start");
+        assert_eq!(nodes[1]["translation"], json!([0.0, 2.875, 0.0]));
+        assert!(nodes[1].get("mesh").is_none());
+
+        let primitives = document["meshes"][0]["primitives"].as_array().unwrap();
+        assert_eq!(primitives.len(), 1);
+        let material = &document["materials"][primitives[0]["material"].as_u64().unwrap() as usize];
+        assert_eq!(material["name"], "TECH28");
+        assert_eq!(document["images"][0]["uri"], "TECH28.png");
+
+        // every buffer view lies within the buffer
+        for view in document["bufferViews"].as_array().unwrap() {
+            let end = view["byteOffset"].as_u64().unwrap() + view["byteLength"].as_u64().unwrap();
+            assert!(end as usize <= bin.len());
+        }
+        let positions = &document["accessors"]
+            [primitives[0]["attributes"]["POSITION"].as_u64().unwrap() as usize];
+        assert_eq!(positions["count"], 24);
+        assert_eq!(positions["max"], json!([2.0, 2.0, 2.0]));
+    }
+
+    #[test]
+    fn test_to_gltf() {
+        let map = from_str(include_str!("../examples/basic.map")).unwrap();
+        let gltf = map.to_gltf().unwrap();
+        let document: Value = serde_json::from_str(&gltf).unwrap();
+
+        let uri = document["buffers"][0]["uri"].as_str().unwrap();
+        let length = document["buffers"][0]["byteLength"].as_u64().unwrap() as usize;
+        let encoded = uri
+            .strip_prefix("data:application/octet-stream;base64,")
+            .unwrap();
+        assert_eq!(encoded.len(), length.div_ceil(3) * 4);
+    }
+
+    #[test]
+    fn test_point_entities_only() {
+        let map = from_str(
+            r#"{
+"classname" "info_player_start"
+"origin" "0 0 32"
+}"#,
+        )
+        .unwrap();
+
+        let glb = map.to_glb().unwrap();
+        let json_length = u32::from_le_bytes(glb[12..16].try_into().unwrap()) as usize;
+        // no BIN chunk after the JSON
+        assert_eq!(glb.len(), 20 + json_length);
+        let document: Value = serde_json::from_slice(&glb[20..]).unwrap();
+        assert!(document.get("buffers").is_none());
+        assert_eq!(document["nodes"][0]["translation"], json!([0.0, 1.0, 0.0]));
+
+        let document: Value = serde_json::from_str(&map.to_gltf().unwrap()).unwrap();
+        assert!(document.get("buffers").is_none());
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+}
@@ -0,0 +1,639 @@
+use crate::geometry::face_windings;
+use crate::meshing::{Epsilons, Plane};
+use crate::raycast::{clip_ray, enter_box, hit, ray, Projection};
+use crate::{Aabb, Brush, Map, RayHit};
+use glam::{DVec3, Vec3};
+use std::collections::HashMap;
+
+/// Brushes a leaf holds when the tree is built.
+const LEAF_SIZE: usize = 4;
+
+/// Bounds that contain nothing and grow to the other box in a union.
+const EMPTY: Aabb = Aabb {
+    min: [f32::MAX; 3],
+    max: [f32::MIN; 3],
+};
+
+/// A bounding volume hierarchy over the brushes of a map, to find the brushes near a point,
+/// inside a box or view frustum, or hit by a ray. Brushes are identified by their entity and
+/// brush index. Brushes that don't enclose a volume are left out, like in [crate::RayCaster].
+#[derive(Debug)]
+pub struct MapIndex {
+    brushes: Vec<Indexed>,
+    /// Positions in `brushes` by entity and brush index.
+    positions: HashMap<(usize, usize), usize>,
+    nodes: Vec<Node>,
+    /// Brushes inserted since the tree was last built.
+    inserted: usize,
+}
+
+#[derive(Debug)]
+struct Indexed {
+    id: (usize, usize),
+    bounds: Aabb,
+    /// The planes of the faces with their polygons.
+    faces: Vec<(Plane, Vec<DVec3>)>,
+    /// The planes and texture projections of all faces, redundant ones included, by face index.
+    planes: Vec<Plane>,
+    projections: Vec<Projection>,
+    /// The leaf holding the brush, `None` once it's removed.
+    leaf: Option<usize>,
+}
+
+#[derive(Debug)]
+struct Node {
+    bounds: Aabb,
+    parent: Option<usize>,
+    kind: NodeKind,
+}
+
+#[derive(Debug)]
+enum NodeKind {
+    Leaf(Vec<usize>),
+    Branch(usize, usize),
+}
+
+impl MapIndex {
+    pub fn new(map: &Map) -> Self {
+        let mut index = Self {
+            brushes: Vec::new(),
+            positions: HashMap::new(),
+            nodes: Vec::new(),
+            inserted: 0,
+        };
+        for (i, entity) in map.entities.iter().enumerate() {
+            for (j, brush) in entity.brushes.iter().enumerate() {
+                if let Some(indexed) = Indexed::new((i, j), brush) {
+                    index.positions.insert((i, j), index.brushes.len());
+                    index.brushes.push(indexed);
+                }
+            }
+        }
+        index.rebuild();
+        index
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// The brushes whose bounds overlap or touch `bounds`.
+    pub fn query_aabb(&self, bounds: &Aabb) -> Vec<(usize, usize)> {
+        let mut found = Vec::new();
+        self.visit(
+            |node| node.intersects(bounds),
+            |brush| {
+                if brush.bounds.intersects(bounds) {
+                    found.push(brush.id);
+                }
+            },
+        );
+        found
+    }
+
+    /// The brushes in the view frustum given by `planes`, each `[x, y, z, d]` with the normal
+    /// pointing out of the frustum and `normal · point <= d` inside, like
+    /// [crate::ConvexHull::planes]. Brushes near the corners of the frustum may be included even
+    /// though they're just outside.
+    pub fn query_frustum(&self, planes: &[[f32; 4]]) -> Vec<(usize, usize)> {
+        let planes = planes
+            .iter()
+            .map(|[x, y, z, d]| (DVec3::new(*x as f64, *y as f64, *z as f64), *d as f64))
+            .collect::<Vec<_>>();
+        let mut found = Vec::new();
+        self.visit(
+            |node| {
+                let (min, max) = (
+                    Vec3::from_array(node.min).as_dvec3(),
+                    Vec3::from_array(node.max).as_dvec3(),
+                );
+                planes.iter().all(|(normal, d)| {
+                    // the corner of the box furthest inside the plane
+                    let corner = DVec3::select(normal.cmpgt(DVec3::ZERO), min, max);
+                    normal.dot(corner) <= *d
+                })
+            },
+            |brush| {
+                let hidden = planes.iter().any(|(normal, d)| {
+                    let mut points = brush.faces.iter().flat_map(|(_, winding)| winding);
+                    points.all(|p| normal.dot(*p) > *d)
+                });
+                if !hidden {
+                    found.push(brush.id);
+                }
+            },
+        );
+        found
+    }
+
+    /// The brush closest to `point` and the distance to it, 0 if the point is inside.
+    pub fn nearest(&self, point: [f32; 3]) -> Option<((usize, usize), f32)> {
+        let root = self.nodes.first()?;
+        let target = Vec3::from_array(point).as_dvec3();
+
+        let mut nearest: Option<((usize, usize), f64)> = None;
+        let mut stack = vec![(0, root.bounds.distance_to_point(point) as f64)];
+        while let Some((n, distance)) = stack.pop() {
+            if nearest.is_some_and(|(_, best)| distance >= best) {
+                continue;
+            }
+            match &self.nodes[n].kind {
+                NodeKind::Leaf(brushes) => {
+                    for brush in brushes.iter().map(|i| &self.brushes[*i]) {
+                        let distance = brush.distance(target);
+                        if nearest.is_none_or(|(_, best)| distance < best) {
+                            nearest = Some((brush.id, distance));
+                        }
+                    }
+                }
+                NodeKind::Branch(a, b) => {
+                    let mut children = [*a, *b].map(|child| {
+                        let bounds = &self.nodes[child].bounds;
+                        (child, bounds.distance_to_point(point) as f64)
+                    });
+                    // visit the closer child first
+                    children.sort_by(|a, b| b.1.total_cmp(&a.1));
+                    stack.extend(children);
+                }
+            }
+        }
+        nearest.map(|(id, distance)| (id, distance as f32))
+    }
+
+    /// The nearest hit of a ray from `origin` along `direction`, like [Map::cast_ray]. Only the
+    /// brushes in nodes the ray passes through are tested, nearest nodes first.
+    pub fn cast_ray(&self, origin: [f32; 3], direction: [f32; 3]) -> Option<RayHit> {
+        let root = self.nodes.first()?;
+        let (origin, direction) = ray(origin, direction)?;
+        let enter = |bounds: &Aabb| {
+            let (min, max) = (Vec3::from_array(bounds.min), Vec3::from_array(bounds.max));
+            enter_box(min.as_dvec3(), max.as_dvec3(), origin, direction)
+        };
+
+        let mut nearest: Option<RayHit> = None;
+        let mut stack = Vec::from_iter(enter(&root.bounds).map(|distance| (0, distance)));
+        while let Some((n, distance)) = stack.pop() {
+            if nearest.is_some_and(|hit| distance > hit.distance as f64) {
+                continue;
+            }
+            match &self.nodes[n].kind {
+                NodeKind::Leaf(brushes) => {
+                    for brush in brushes.iter().map(|i| &self.brushes[*i]) {
+                        let (distance, face) = match clip_ray(&brush.planes, origin, direction) {
+                            Some(clipped) => clipped,
+                            None => continue,
+                        };
+                        if nearest.is_none_or(|hit| distance < hit.distance as f64) {
+                            let face_hit = hit(
+                                face,
+                                &brush.projections[face],
+                                &brush.planes[face],
+                                origin,
+                                direction,
+                                distance,
+                            );
+                            let (entity, brush) = brush.id;
+                            nearest = Some(RayHit {
+                                entity,
+                                brush,
+                                ..face_hit
+                            });
+                        }
+                    }
+                }
+                NodeKind::Branch(a, b) => {
+                    let mut children = [*a, *b]
+                        .into_iter()
+                        .filter_map(|child| Some((child, enter(&self.nodes[child].bounds)?)))
+                        .collect::<Vec<_>>();
+                    // visit the closer child first
+                    children.sort_by(|a, b| b.1.total_cmp(&a.1));
+                    stack.extend(children);
+                }
+            }
+        }
+        nearest
+    }
+
+    /// Updates the bounds of brush `brush` of entity `entity` after it was edited, or adds it if
+    /// it's new. The tree is refitted rather than rebuilt, call [MapIndex::rebuild] after moving
+    /// many brushes far. A brush that no longer encloses a volume is removed.
+    pub fn update(&mut self, entity: usize, brush: usize, edited: &Brush) {
+        let indexed = match Indexed::new((entity, brush), edited) {
+            Some(indexed) => indexed,
+            None => return self.remove(entity, brush),
+        };
+
+        match self.positions.get(&(entity, brush)) {
+            Some(i) => {
+                let leaf = self.brushes[*i].leaf;
+                self.brushes[*i] = Indexed { leaf, ..indexed };
+                if let Some(leaf) = leaf {
+                    self.refit(leaf);
+                }
+            }
+            None => {
+                let i = self.brushes.len();
+                self.positions.insert((entity, brush), i);
+                self.brushes.push(indexed);
+                self.insert(i);
+            }
+        }
+    }
+
+    /// Removes brush `brush` of entity `entity`. The other brushes keep their indices.
+    pub fn remove(&mut self, entity: usize, brush: usize) {
+        let i = match self.positions.remove(&(entity, brush)) {
+            Some(i) => i,
+            None => return,
+        };
+        if let Some(leaf) = self.brushes[i].leaf.take() {
+            if let NodeKind::Leaf(brushes) = &mut self.nodes[leaf].kind {
+                brushes.retain(|b| *b != i);
+            }
+            self.refit(leaf);
+        }
+    }
+
+    /// Builds the tree anew, splitting the brushes at the median of their centers along the
+    /// longest axis.
+    pub fn rebuild(&mut self) {
+        // drop the removed brushes
+        let brushes = std::mem::take(&mut self.brushes);
+        self.brushes = brushes
+            .into_iter()
+            .enumerate()
+            .filter(|(i, brush)| self.positions.get(&brush.id) == Some(i))
+            .map(|(_, brush)| brush)
+            .collect();
+        self.positions = self
+            .brushes
+            .iter()
+            .enumerate()
+            .map(|(i, brush)| (brush.id, i))
+            .collect();
+
+        self.nodes.clear();
+        self.inserted = 0;
+        let mut order = (0..self.brushes.len()).collect::<Vec<_>>();
+        self.build(&mut order, None);
+    }
+
+    fn build(&mut self, order: &mut [usize], parent: Option<usize>) -> usize {
+        let n = self.nodes.len();
+        let bounds = order
+            .iter()
+            .fold(EMPTY, |b, i| b.union(&self.brushes[*i].bounds));
+        self.nodes.push(Node {
+            bounds,
+            parent,
+            kind: NodeKind::Leaf(Vec::new()),
+        });
+
+        if order.len() <= LEAF_SIZE {
+            for i in order.iter() {
+                self.brushes[*i].leaf = Some(n);
+            }
+            self.nodes[n].kind = NodeKind::Leaf(order.to_vec());
+            return n;
+        }
+
+        let centers = order.iter().fold(EMPTY, |b, i| {
+            let center = self.brushes[*i].bounds.center();
+            b.union(&Aabb {
+                min: center,
+                max: center,
+            })
+        });
+        let size = centers.size();
+        let axis = (0..3).fold(0, |a, b| if size[b] > size[a] { b } else { a });
+        order.sort_by(|a, b| {
+            let (a, b) = (&self.brushes[*a].bounds, &self.brushes[*b].bounds);
+            a.center()[axis].total_cmp(&b.center()[axis])
+        });
+
+        let (left, right) = order.split_at_mut(order.len() / 2);
+        let left = self.build(left, Some(n));
+        let right = self.build(right, Some(n));
+        self.nodes[n].kind = NodeKind::Branch(left, right);
+        n
+    }
+
+    /// Adds brush `i` to the leaf whose bounds grow the least, rebuilding once the tree has
+    /// taken in many brushes this way.
+    fn insert(&mut self, i: usize) {
+        if self.nodes.is_empty() || self.inserted >= self.brushes.len() / 2 {
+            self.rebuild();
+            return;
+        }
+
+        let bounds = self.brushes[i].bounds;
+        let mut n = 0;
+        while let NodeKind::Branch(a, b) = self.nodes[n].kind {
+            let growth = |child: usize| {
+                let node = &self.nodes[child].bounds;
+                surface(&node.union(&bounds)) - surface(node)
+            };
+            n = if growth(a) <= growth(b) { a } else { b };
+        }
+
+        if let NodeKind::Leaf(brushes) = &mut self.nodes[n].kind {
+            brushes.push(i);
+        }
+        self.brushes[i].leaf = Some(n);
+        self.inserted += 1;
+        self.refit(n);
+    }
+
+    /// Recomputes the bounds from node `n` up to the root.
+    fn refit(&mut self, n: usize) {
+        let mut current = Some(n);
+        while let Some(n) = current {
+            let bounds = match &self.nodes[n].kind {
+                NodeKind::Leaf(brushes) => brushes
+                    .iter()
+                    .fold(EMPTY, |b, i| b.union(&self.brushes[*i].bounds)),
+                NodeKind::Branch(a, b) => self.nodes[*a].bounds.union(&self.nodes[*b].bounds),
+            };
+            self.nodes[n].bounds = bounds;
+            current = self.nodes[n].parent;
+        }
+    }
+
+    /// Calls `found` for the brushes in the leaves reached through nodes `enter` accepts.
+    fn visit(&self, enter: impl Fn(&Aabb) -> bool, mut found: impl FnMut(&Indexed)) {
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(n) = stack.pop() {
+            let node = &self.nodes[n];
+            if !enter(&node.bounds) {
+                continue;
+            }
+            match &node.kind {
+                NodeKind::Leaf(brushes) => {
+                    for i in brushes {
+                        found(&self.brushes[*i]);
+                    }
+                }
+                NodeKind::Branch(a, b) => stack.extend([*a, *b]),
+            }
+        }
+    }
+}
+
+impl Indexed {
+    /// `None` for brushes that don't enclose a volume.
+    fn new(id: (usize, usize), brush: &Brush) -> Option<Self> {
+        let bounds = brush.bounds().ok()?;
+        let planes = brush.planes().ok()?;
+        let faces = face_windings(&planes, &Epsilons::default())
+            .ok()?
+            .into_iter()
+            .map(|(i, winding)| (planes[i].clone(), winding))
+            .collect();
+        Some(Self {
+            id,
+            bounds,
+            faces,
+            projections: brush.faces.iter().map(Projection::new).collect(),
+            planes,
+            leaf: None,
+        })
+    }
+
+    /// Distance from `point` to the closest point of the brush, which lies on one of the faces
+    /// the point is in front of.
+    fn distance(&self, point: DVec3) -> f64 {
+        let mut nearest = f64::MAX;
+        for (plane, winding) in &self.faces {
+            let height = plane.distance(point);
+            if height <= 0.0 {
+                continue;
+            }
+
+            let projected = point - plane.normal * height;
+            let edges = (0..winding.len()).map(|n| (winding[n], winding[(n + 1) % winding.len()]));
+            let inside = edges
+                .clone()
+                .all(|(a, b)| (b - a).cross(projected - a).dot(plane.normal) >= 0.0);
+            let distance = if inside {
+                height
+            } else {
+                edges
+                    .map(|(a, b)| {
+                        let t = ((point - a).dot(b - a) / (b - a).length_squared()).clamp(0.0, 1.0);
+                        point.distance(a + (b - a) * t)
+                    })
+                    .fold(f64::MAX, f64::min)
+            };
+            nearest = nearest.min(distance);
+        }
+
+        if nearest == f64::MAX {
+            // behind every face
+            0.0
+        } else {
+            nearest
+        }
+    }
+}
+
+/// Half the surface area of a box, for comparing how much boxes grow.
+fn surface(bounds: &Aabb) -> f32 {
+    let [x, y, z] = bounds.size().map(|s| s.max(0.0));
+    x * y + y * z + z * x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MapBuilder;
+
+    /// A 10 by 10 grid of 16 unit cubes, 32 units apart, in the worldspawn.
+    fn grid() -> Map {
+        let mut builder = MapBuilder::new();
+        for x in 0..10 {
+            for y in 0..10 {
+                let min = [x as f32 * 32.0, y as f32 * 32.0, 0.0];
+                let max = [min[0] + 16.0, min[1] + 16.0, 16.0];
+                builder = builder.brush(Brush::cuboid(min, max, "BOX").unwrap());
+            }
+        }
+        builder
+            .point_entity("info_player_start", [0.0, 0.0, 24.0], &[])
+            .build()
+    }
+
+    fn brute_force_aabb(map: &Map, bounds: &Aabb) -> Vec<(usize, usize)> {
+        let mut found = Vec::new();
+        for (i, entity) in map.entities.iter().enumerate() {
+            for (j, brush) in entity.brushes.iter().enumerate() {
+                if brush.bounds().unwrap().intersects(bounds) {
+                    found.push((i, j));
+                }
+            }
+        }
+        found
+    }
+
+    fn sorted(mut ids: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn test_query_aabb() {
+        let map = grid();
+        let index = MapIndex::new(&map);
+        assert_eq!(index.len(), 100);
+
+        for bounds in [
+            Aabb {
+                min: [40.0, 40.0, 0.0],
+                max: [100.0, 70.0, 4.0],
+            },
+            Aabb {
+                min: [17.0, 17.0, 0.0],
+                max: [31.0, 31.0, 16.0],
+            },
+            Aabb {
+                min: [-100.0; 3],
+                max: [1000.0; 3],
+            },
+        ] {
+            assert_eq!(
+                sorted(index.query_aabb(&bounds)),
+                brute_force_aabb(&map, &bounds)
+            );
+        }
+    }
+
+    #[test]
+    fn test_nearest() {
+        let index = MapIndex::new(&grid());
+
+        // inside cube (2, 3)
+        assert_eq!(index.nearest([72.0, 100.0, 8.0]), Some(((0, 23), 0.0)));
+        // above cube (9, 9)
+        assert_eq!(index.nearest([296.0, 296.0, 26.0]), Some(((0, 99), 10.0)));
+        // between cubes (0, 0) and (1, 0), closer to the first
+        assert_eq!(index.nearest([20.0, 8.0, 8.0]), Some(((0, 0), 4.0)));
+        // off a corner of cube (0, 0)
+        let (id, distance) = index.nearest([-3.0, -4.0, 8.0]).unwrap();
+        assert_eq!((id, distance), ((0, 0), 5.0));
+    }
+
+    #[test]
+    fn test_cast_ray() {
+        let map = grid();
+        let index = MapIndex::new(&map);
+
+        for (origin, direction) in [
+            // along the first row, hitting cube (0, 0) from the side
+            ([-50.0, 8.0, 8.0], [1.0, 0.0, 0.0]),
+            // down onto cube (4, 5)
+            ([136.0, 168.0, 100.0], [0.0, 0.0, -1.0]),
+            // diagonally across the grid
+            ([-20.0, -10.0, 40.0], [3.0, 2.0, -0.5]),
+            // between the rows
+            ([-50.0, 24.0, 8.0], [1.0, 0.0, 0.0]),
+            // away from the grid
+            ([-50.0, 8.0, 8.0], [-1.0, 0.0, 0.0]),
+        ] {
+            assert_eq!(
+                index.cast_ray(origin, direction),
+                map.cast_ray(origin, direction)
+            );
+        }
+
+        let hit = index
+            .cast_ray([136.0, 168.0, 100.0], [0.0, 0.0, -1.0])
+            .unwrap();
+        assert_eq!((hit.entity, hit.brush, hit.distance), (0, 45, 84.0));
+        assert_eq!(index.cast_ray([-50.0, 24.0, 8.0], [1.0, 0.0, 0.0]), None);
+    }
+
+    #[test]
+    fn test_query_frustum() {
+        let index = MapIndex::new(&grid());
+        // a box around the first two rows, with a slanted side cutting off cube (3, 0) but not
+        // cube (3, 1)
+        let planes = [
+            [-1.0, 0.0, 0.0, 0.0],
+            [0.0, -1.0, 0.0, 0.0],
+            [0.0, 0.0, -1.0, 0.0],
+            [0.0, 0.0, 1.0, 16.0],
+            [0.0, 1.0, 0.0, 48.0],
+            [1.0, -1.0, 0.0, 70.0],
+        ];
+        assert_eq!(
+            sorted(index.query_frustum(&planes)),
+            vec![(0, 0), (0, 1), (0, 10), (0, 11), (0, 20), (0, 21), (0, 31)]
+        );
+    }
+
+    #[test]
+    fn test_incremental_updates() {
+        let mut map = grid();
+        let mut index = MapIndex::new(&map);
+
+        // move cube (0, 0) far out
+        map.entities[0].brushes[0] = Brush::cuboid([500.0; 3], [516.0; 3], "BOX").unwrap();
+        index.update(0, 0, &map.entities[0].brushes[0]);
+        let far = Aabb {
+            min: [400.0; 3],
+            max: [600.0; 3],
+        };
+        assert_eq!(index.query_aabb(&far), vec![(0, 0)]);
+        assert_eq!(index.nearest([0.0, 0.0, 8.0]).unwrap().0, (0, 1));
+
+        // a new door
+        let door = Brush::cuboid([-64.0, 0.0, 0.0], [-56.0, 64.0, 96.0], "DOOR").unwrap();
+        index.update(2, 0, &door);
+        assert_eq!(index.len(), 101);
+        assert_eq!(index.nearest([-80.0, 32.0, 8.0]), Some(((2, 0), 16.0)));
+
+        index.remove(0, 0);
+        assert!(index.query_aabb(&far).is_empty());
+        assert_eq!(index.len(), 100);
+
+        index.rebuild();
+        assert_eq!(index.len(), 100);
+        assert_eq!(index.nearest([-80.0, 32.0, 8.0]), Some(((2, 0), 16.0)));
+        let everything = Aabb {
+            min: [-1000.0; 3],
+            max: [1000.0; 3],
+        };
+        assert_eq!(index.query_aabb(&everything).len(), 100);
+
+        // a brush edited into an open one drops out
+        let mut open = Brush::cuboid([-64.0, 0.0, 0.0], [-56.0, 64.0, 96.0], "DOOR").unwrap();
+        open.faces.pop();
+        index.update(2, 0, &open);
+        index.update(0, 5, &Brush::default());
+        assert_eq!(index.len(), 98);
+        assert_eq!(index.nearest([-80.0, 32.0, 8.0]).unwrap().0, (0, 1));
+    }
+
+    #[test]
+    fn test_unusable_brushes_are_left_out() {
+        let mut map = grid();
+        let mut open = Brush::cuboid([-64.0, 0.0, 0.0], [-56.0, 64.0, 96.0], "DOOR").unwrap();
+        open.faces.pop();
+        map.entities[0].brushes.push(open);
+        map.entities[0].brushes.push(Brush::default());
+
+        let index = MapIndex::new(&map);
+        assert_eq!(index.len(), 100);
+        assert_eq!(
+            index.cast_ray([-80.0, 8.0, 8.0], [1.0, 0.0, 0.0]),
+            map.cast_ray([-80.0, 8.0, 8.0], [1.0, 0.0, 0.0])
+        );
+    }
+}
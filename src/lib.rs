@@ -31,14 +31,59 @@
 //! println!("{:#?}", map);
 //! ```
 
+#[cfg(feature = "meshing")]
+mod alignment;
+#[cfg(feature = "meshing")]
+pub mod bsp;
+#[cfg(feature = "meshing")]
+mod collision;
+#[cfg(feature = "meshing")]
+mod construction;
+#[cfg(feature = "meshing")]
+mod geometry;
+#[cfg(feature = "gltf")]
+mod gltf;
+#[cfg(feature = "meshing")]
+mod index;
+#[cfg(feature = "lighting")]
+pub mod lighting;
 #[cfg(feature = "meshing")]
 pub mod meshing;
-
+#[cfg(feature = "meshing")]
+mod obj;
 mod parsers;
+#[cfg(feature = "meshing")]
+mod raycast;
+#[cfg(feature = "meshing")]
+mod transform;
 mod types;
+#[cfg(feature = "meshing")]
+mod validation;
 
+#[cfg(feature = "meshing")]
+pub use alignment::StandardAlignment;
+#[cfg(feature = "meshing")]
+pub use collision::ConvexHull;
+#[cfg(feature = "meshing")]
+pub use construction::MapBuilder;
+#[cfg(feature = "meshing")]
+pub use geometry::Aabb;
+#[cfg(feature = "meshing")]
+pub use glam;
+#[cfg(feature = "gltf")]
+pub use gltf::GltfOptions;
+#[cfg(feature = "meshing")]
+pub use index::MapIndex;
 #[cfg(feature = "meshing")]
 pub use meshing::Mesh;
+#[cfg(feature = "meshing")]
+pub use obj::{Obj, ObjImportOptions, ObjOptions};
 
 pub use parsers::{from_bytes, from_reader, from_str};
+#[cfg(feature = "meshing")]
+pub use raycast::{RayCaster, RayHit};
+#[cfg(feature = "meshing")]
+pub use transform::TextureLock;
 pub use types::*;
+#[cfg(feature = "meshing")]
+pub use validation::{Diagnostic, DiagnosticKind};
@@ -0,0 +1,680 @@
+//! Bakes direct lighting from Quake light entities into lightmaps on the CPU.
+
+use crate::meshing::{
+    is_world, LightmapAtlas, LightmapOptions, LightmapRect, MeshOptions, Occluder,
+};
+use crate::{Entity, Map, Mesh};
+use anyhow::{anyhow, Result};
+use glam::Vec3;
+use std::collections::HashMap;
+
+/// Light level of a `light` entity without a `light` key.
+const DEFAULT_LIGHT: f32 = 300.0;
+/// Distance over which the inverse falloffs drop to the light level, as in ericw-tools.
+const FALLOFF_SCALE: f32 = 128.0;
+/// Full angle of a spotlight cone without a `_cone` or `angle` key.
+const DEFAULT_CONE: f32 = 40.0;
+/// Distance the inverse falloffs treat closer luxels as, so they don't divide by zero.
+const MIN_DISTANCE: f32 = 1.0;
+/// How far luxels are lifted off their face before tracing, so they don't hit their own brush.
+const SAMPLE_OFFSET: f32 = 0.5;
+/// Length of the rays traced towards the sun.
+const SUN_DISTANCE: f32 = 65536.0;
+
+/// How the light level of a [Light] decreases with distance, selected by its `delay` key.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Falloff {
+    /// `delay 0`: the light level minus the distance scaled by `wait`.
+    #[default]
+    Linear,
+    /// `delay 1`: the light level over the distance.
+    Inverse,
+    /// `delay 2`: the light level over the square of the distance.
+    InverseSquare,
+    /// `delay 3`, and `delay 4` which ericw-tools uses for local minlight: the light level at any
+    /// distance.
+    None,
+    /// `delay 5`: like [Falloff::InverseSquare], but never brighter than the light level.
+    InverseSquareClamped,
+}
+
+/// Restricts a [Light] to a cone, for lights with a `mangle` or `target`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Spot {
+    pub direction: [f32; 3],
+    /// Full angle of the cone in degrees, from `_cone` or `angle`.
+    pub cone: f32,
+    /// Full angle in degrees of the fully lit part of the cone, from `_softangle`. Zero for a hard
+    /// edge.
+    pub soft_cone: f32,
+}
+
+/// A point light read from a `light*` entity.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Light {
+    pub origin: [f32; 3],
+    pub light: f32,
+    /// Color from `_color`, in the range 0 to 1.
+    pub color: [f32; 3],
+    /// Scale of the falloff distance from `wait`.
+    pub wait: f32,
+    pub falloff: Falloff,
+    /// How much the angle of incidence darkens a surface, from `_anglescale`.
+    pub angle_scale: f32,
+    pub spot: Option<Spot>,
+}
+
+/// Parallel light from `_sunlight` and `_sun_mangle` on the worldspawn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sun {
+    /// Direction the light travels in.
+    pub direction: [f32; 3],
+    pub light: f32,
+    pub color: [f32; 3],
+    pub angle_scale: f32,
+}
+
+/// All lights of a map.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Lights {
+    pub lights: Vec<Light>,
+    pub sun: Option<Sun>,
+    /// Light level no luxel goes below, from the worldspawn's `light` or `_minlight`.
+    pub min_light: f32,
+    /// Light entities that couldn't be read and were left out, by entity index and reason.
+    pub skipped: Vec<(usize, String)>,
+}
+
+impl Lights {
+    /// Reads the light entities and the worldspawn lighting keys of `map`. Lights without an
+    /// origin or with an unsupported `delay` end up in [Lights::skipped].
+    pub fn from_map(map: &Map) -> Self {
+        let mut lights = Self::default();
+        let targets = map
+            .entities
+            .iter()
+            .filter_map(|entity| Some((entity.properties.get("targetname")?, entity)))
+            .collect::<HashMap<_, _>>();
+
+        for (i, entity) in map.entities.iter().enumerate() {
+            let classname = entity.properties.get("classname").map(String::as_str);
+            if classname == Some("worldspawn") {
+                lights.min_light = float(entity, "_minlight")
+                    .or_else(|| float(entity, "light"))
+                    .unwrap_or(0.0);
+                lights.sun = Sun::from_entity(entity);
+            } else if classname.is_some_and(|c| c.starts_with("light")) {
+                let mut light = match Light::from_entity(entity) {
+                    Ok(light) => light,
+                    Err(err) => {
+                        lights.skipped.push((i, err.to_string()));
+                        continue;
+                    }
+                };
+                let target = entity
+                    .properties
+                    .get("target")
+                    .and_then(|target| targets.get(target))
+                    .and_then(|target| target.vector("origin"))
+                    .map(Vec3::from_array);
+                if let Some(target) = target {
+                    let direction = target - Vec3::from_array(light.origin);
+                    let direction = direction.normalize_or_zero();
+                    let spot = light
+                        .spot
+                        .get_or_insert_with(|| Spot::new(entity, direction));
+                    spot.direction = direction.to_array();
+                }
+                lights.lights.push(light);
+            }
+        }
+
+        lights
+    }
+}
+
+impl Spot {
+    fn new(entity: &Entity, direction: Vec3) -> Self {
+        Self {
+            direction: direction.to_array(),
+            cone: float(entity, "_cone")
+                .or_else(|| float(entity, "angle"))
+                .unwrap_or(DEFAULT_CONE),
+            soft_cone: float(entity, "_softangle").unwrap_or(0.0),
+        }
+    }
+}
+
+impl Light {
+    /// Reads a light from the keys of `entity`. Targets aren't resolved, see [Lights::from_map].
+    pub fn from_entity(entity: &Entity) -> Result<Self> {
+        let origin = entity
+            .vector("origin")
+            .ok_or_else(|| anyhow!("light has no origin"))?;
+        let falloff = match float(entity, "delay").unwrap_or(0.0) as i32 {
+            0 => Falloff::Linear,
+            1 => Falloff::Inverse,
+            2 => Falloff::InverseSquare,
+            3 | 4 => Falloff::None,
+            5 => Falloff::InverseSquareClamped,
+            delay => return Err(anyhow!("unsupported delay {}", delay)),
+        };
+
+        let spot = mangle(entity, "mangle").map(|direction| Spot::new(entity, direction));
+
+        Ok(Self {
+            origin,
+            light: float(entity, "light").unwrap_or(DEFAULT_LIGHT),
+            color: color(entity, "_color"),
+            wait: float(entity, "wait").unwrap_or(1.0),
+            falloff,
+            angle_scale: float(entity, "_anglescale").unwrap_or(0.5),
+            spot,
+        })
+    }
+
+    /// Light level at `distance` from the light, before the angle of incidence is applied.
+    pub fn level(&self, distance: f32) -> f32 {
+        let scaled = distance * self.wait / FALLOFF_SCALE;
+        let near = distance.max(MIN_DISTANCE) * self.wait / FALLOFF_SCALE;
+        let level = match self.falloff {
+            Falloff::Linear => self.light - distance * self.wait,
+            Falloff::Inverse => self.light / near,
+            Falloff::InverseSquare => self.light / (near * near),
+            Falloff::None => self.light,
+            Falloff::InverseSquareClamped => self.light / ((scaled + 1.0) * (scaled + 1.0)),
+        };
+        level.max(0.0)
+    }
+
+    /// How much of the light reaches `direction` from the light, 1 outside of spotlights.
+    fn spot_factor(&self, direction: Vec3) -> f32 {
+        let spot = match &self.spot {
+            Some(spot) => spot,
+            None => return 1.0,
+        };
+
+        let cos = direction.dot(Vec3::from_array(spot.direction));
+        let outer = (spot.cone * 0.5).to_radians().cos();
+        let inner = (spot.soft_cone.min(spot.cone) * 0.5).to_radians().cos();
+        if cos <= outer {
+            0.0
+        } else if spot.soft_cone <= 0.0 || cos >= inner {
+            1.0
+        } else {
+            (cos - outer) / (inner - outer)
+        }
+    }
+}
+
+impl Sun {
+    /// Reads the sun from the keys of the worldspawn, `None` if there's no `_sunlight`.
+    pub fn from_entity(entity: &Entity) -> Option<Self> {
+        let light = float(entity, "_sunlight").filter(|light| *light > 0.0)?;
+
+        Some(Self {
+            // straight down unless told otherwise
+            direction: mangle(entity, "_sun_mangle")
+                .unwrap_or(Vec3::NEG_Z)
+                .to_array(),
+            light,
+            color: color(entity, "_sunlight_color"),
+            angle_scale: float(entity, "_anglescale").unwrap_or(0.5),
+        })
+    }
+}
+
+/// Options for [bake].
+#[derive(Clone, Copy, Default)]
+pub struct LightingOptions<'a> {
+    pub mesh: MeshOptions<'a>,
+    pub lightmap: LightmapOptions,
+}
+
+/// Linear RGB luxels of a lightmap atlas, row by row. A level of 1 is a Quake light level of
+/// 255, the brightest a Quake lightmap can hold.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LightmapImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[f32; 3]>,
+}
+
+impl LightmapImage {
+    /// Converts the luxels to 8-bit RGBA, clamping anything brighter than 1.
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|p| {
+                let [r, g, b] = p.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8);
+                [r, g, b, 255]
+            })
+            .collect()
+    }
+}
+
+/// The meshes of a lit entity with their lightmap UVs, and the lightmap they index into.
+#[derive(Debug, Default, PartialEq)]
+pub struct BakedLighting {
+    /// One mesh per texture name, as from [Mesh::from_entity_by_texture_with].
+    pub meshes: HashMap<String, Mesh>,
+    pub atlas: LightmapAtlas,
+    pub image: LightmapImage,
+    /// Light entities that were left out, see [Lights::skipped].
+    pub skipped_lights: Vec<(usize, String)>,
+}
+
+/// Meshes `map.entities[entity]` and raytraces the direct light of all lights of `map` onto it.
+/// The brushes of the entity and of the world, the worldspawn and the `func_group` and
+/// `func_detail` entities, cast shadows.
+pub fn bake(map: &Map, entity: usize, options: &LightingOptions) -> Result<BakedLighting> {
+    let lit = map
+        .entities
+        .get(entity)
+        .ok_or_else(|| anyhow!("map has no entity {}", entity))?;
+    let lights = Lights::from_map(map);
+
+    let occluders = Occluder::from_map(map, &options.mesh, |i, other| {
+        i == entity || is_world(other)
+    });
+
+    let (meshes, atlas) =
+        Mesh::from_entity_by_texture_with_lightmap(lit, &options.mesh, &options.lightmap)?;
+
+    let mut image = LightmapImage {
+        width: atlas.width,
+        height: atlas.height,
+        pixels: vec![[0.0; 3]; (atlas.width * atlas.height) as usize],
+    };
+    for rect in &atlas.rects {
+        bake_rect(rect, &lights, &occluders, &mut image);
+        dilate_rect(rect, options.lightmap.padding, &mut image);
+    }
+
+    Ok(BakedLighting {
+        meshes,
+        atlas,
+        image,
+        skipped_lights: lights.skipped,
+    })
+}
+
+fn bake_rect(
+    rect: &LightmapRect,
+    lights: &Lights,
+    occluders: &[Occluder],
+    image: &mut LightmapImage,
+) {
+    let normal = Vec3::from_array(rect.normal);
+    let origin = Vec3::from_array(rect.origin) + normal * SAMPLE_OFFSET;
+    let step_s = Vec3::from_array(rect.step_s);
+    let step_t = Vec3::from_array(rect.step_t);
+    let blocked = |from: Vec3, to: Vec3| occluders.iter().any(|o| o.blocks(from, to));
+
+    for t in 0..rect.height {
+        for s in 0..rect.width {
+            let sample = origin + step_s * s as f32 + step_t * t as f32;
+            let mut color = Vec3::ZERO;
+
+            for light in &lights.lights {
+                let to_light = Vec3::from_array(light.origin) - sample;
+                let distance = to_light.length();
+                let cos = normal.dot(to_light / distance);
+                if cos <= 0.0 {
+                    continue;
+                }
+
+                let level = light.level(distance)
+                    * light.spot_factor(-to_light / distance)
+                    * incidence(cos, light.angle_scale);
+                if level > 0.0 && !blocked(sample, Vec3::from_array(light.origin)) {
+                    color += Vec3::from_array(light.color) * level;
+                }
+            }
+
+            if let Some(sun) = &lights.sun {
+                let to_sun = -Vec3::from_array(sun.direction).normalize_or_zero();
+                let cos = normal.dot(to_sun);
+                if cos > 0.0 && !blocked(sample, sample + to_sun * SUN_DISTANCE) {
+                    color +=
+                        Vec3::from_array(sun.color) * sun.light * incidence(cos, sun.angle_scale);
+                }
+            }
+
+            let color = color.max(Vec3::splat(lights.min_light)) / 255.0;
+            let index = (rect.y + t) * image.width + rect.x + s;
+            image.pixels[index as usize] = color.to_array();
+        }
+    }
+}
+
+/// Copies the edge luxels of `rect` into its padding, so filtering at the edges of the face
+/// doesn't pick up black.
+fn dilate_rect(rect: &LightmapRect, padding: u32, image: &mut LightmapImage) {
+    let (x0, y0) = (
+        rect.x.saturating_sub(padding),
+        rect.y.saturating_sub(padding),
+    );
+    let x1 = (rect.x + rect.width + padding).min(image.width);
+    let y1 = (rect.y + rect.height + padding).min(image.height);
+
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let sx = x.clamp(rect.x, rect.x + rect.width - 1);
+            let sy = y.clamp(rect.y, rect.y + rect.height - 1);
+            if (sx, sy) != (x, y) {
+                image.pixels[(y * image.width + x) as usize] =
+                    image.pixels[(sy * image.width + sx) as usize];
+            }
+        }
+    }
+}
+
+/// Scales light by the angle it hits a surface at, `angle_scale` 0 ignoring the angle entirely.
+fn incidence(cos: f32, angle_scale: f32) -> f32 {
+    1.0 - angle_scale + angle_scale * cos
+}
+
+fn float(entity: &Entity, key: &str) -> Option<f32> {
+    entity.properties.get(key)?.trim().parse().ok()
+}
+
+/// Colors are either 0 to 1 or 0 to 255, white if missing.
+fn color(entity: &Entity, key: &str) -> [f32; 3] {
+    match entity.vector(key).map(Vec3::from_array) {
+        Some(color) if color.max_element() > 1.0 => (color / 255.0).to_array(),
+        Some(color) => color.to_array(),
+        None => [1.0; 3],
+    }
+}
+
+/// Direction from a "yaw pitch roll" key in degrees, pitch 90 pointing straight up.
+fn mangle(entity: &Entity, key: &str) -> Option<Vec3> {
+    let [yaw, pitch, _] = entity.vector(key)?.map(f32::to_radians);
+    Some(Vec3::new(
+        pitch.cos() * yaw.cos(),
+        pitch.cos() * yaw.sin(),
+        pitch.sin(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_str;
+    use crate::meshing::TextureClass;
+
+    /// A floor with a box floating above its center and a light above the box.
+    const ROOM: &str = r#"{
+"classname" "worldspawn"
+{
+( -128 0 0 ) ( -128 1 0 ) ( -128 0 1 ) FLOOR [ 0 1 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 128 0 0 ) ( 128 0 1 ) ( 128 1 0 ) FLOOR [ 0 1 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 0 -128 0 ) ( 0 -128 1 ) ( 1 -128 0 ) FLOOR [ 1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 0 128 0 ) ( 1 128 0 ) ( 0 128 1 ) FLOOR [ 1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 0 0 -16 ) ( 1 0 -16 ) ( 0 1 -16 ) FLOOR [ 1 0 0 0 ] [ 0 -1 0 0 ] 0 1 1
+( 0 0 0 ) ( 0 1 0 ) ( 1 0 0 ) FLOOR [ 1 0 0 0 ] [ 0 -1 0 0 ] 0 1 1
+}
+{
+( -16 0 0 ) ( -16 1 0 ) ( -16 0 1 ) BOX [ 0 1 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 16 0 0 ) ( 16 0 1 ) ( 16 1 0 ) BOX [ 0 1 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 0 -16 0 ) ( 0 -16 1 ) ( 1 -16 0 ) BOX [ 1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 0 16 0 ) ( 1 16 0 ) ( 0 16 1 ) BOX [ 1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 0 0 32 ) ( 1 0 32 ) ( 0 1 32 ) BOX [ 1 0 0 0 ] [ 0 -1 0 0 ] 0 1 1
+( 0 0 48 ) ( 0 1 48 ) ( 1 0 48 ) BOX [ 1 0 0 0 ] [ 0 -1 0 0 ] 0 1 1
+}
+}
+{
+"classname" "light"
+"origin" "0 0 64"
+"light" "200"
+}"#;
+
+    /// Light level of the luxel of `rect` closest to `position`.
+    fn luxel(baked: &BakedLighting, rect: &LightmapRect, position: Vec3) -> f32 {
+        let offset = position - Vec3::from_array(rect.origin);
+        let step_s = Vec3::from_array(rect.step_s);
+        let step_t = Vec3::from_array(rect.step_t);
+        let s = (offset.dot(step_s) / step_s.length_squared()).round() as u32;
+        let t = (offset.dot(step_t) / step_t.length_squared()).round() as u32;
+        let index = (rect.y + t) * baked.image.width + rect.x + s;
+        baked.image.pixels[index as usize][0] * 255.0
+    }
+
+    #[test]
+    fn test_bake() {
+        let map = from_str(ROOM).unwrap();
+        let baked = bake(&map, 0, &LightingOptions::default()).unwrap();
+
+        assert_eq!(baked.meshes.len(), 2);
+        assert_eq!(
+            (baked.image.width, baked.image.height),
+            (baked.atlas.width, baked.atlas.height)
+        );
+        for mesh in baked.meshes.values() {
+            assert!(mesh.uvs_lightmap.is_some());
+        }
+
+        let floor = baked
+            .atlas
+            .rects
+            .iter()
+            .find(|r| r.brush == 0 && r.normal == [0.0, 0.0, 1.0])
+            .unwrap();
+
+        // in the shadow of the box
+        assert_eq!(luxel(&baked, floor, Vec3::ZERO), 0.0);
+
+        // linear falloff at an angle
+        let position = Vec3::new(96.0, 0.0, 0.0);
+        let to_light = Vec3::new(0.0, 0.0, 64.0) - (position + Vec3::Z * SAMPLE_OFFSET);
+        let expected = (200.0 - to_light.length()) * (0.5 + 0.5 * to_light.normalize().z);
+        assert!((luxel(&baked, floor, position) - expected).abs() < 0.01);
+
+        // the top of the box faces the light
+        let top = baked
+            .atlas
+            .rects
+            .iter()
+            .find(|r| r.brush == 1 && r.normal == [0.0, 0.0, 1.0])
+            .unwrap();
+        let expected = 200.0 - (64.0 - 48.0 - SAMPLE_OFFSET);
+        assert!((luxel(&baked, top, Vec3::new(0.0, 0.0, 48.0)) - expected).abs() < 0.01);
+
+        let rgba = baked.image.to_rgba8();
+        assert_eq!(rgba.len(), baked.image.pixels.len() * 4);
+    }
+
+    #[test]
+    fn test_bake_sun_and_min_light() {
+        let mut map = from_str(&ROOM.replacen(
+            r#""classname" "worldspawn""#,
+            r#""classname" "worldspawn"
+"_sunlight" "100"
+"_sun_mangle" "0 -90 0"
+"light" "10""#,
+            1,
+        ))
+        .unwrap();
+        map.entities.truncate(1);
+        let baked = bake(&map, 0, &LightingOptions::default()).unwrap();
+
+        let floor = baked
+            .atlas
+            .rects
+            .iter()
+            .find(|r| r.brush == 0 && r.normal == [0.0, 0.0, 1.0])
+            .unwrap();
+        assert_eq!(luxel(&baked, floor, Vec3::ZERO), 10.0);
+        assert!((luxel(&baked, floor, Vec3::new(96.0, 0.0, 0.0)) - 100.0).abs() < 0.01);
+
+        // a box that's only clipping doesn't cast a shadow
+        let classes = HashMap::from([("BOX".to_string(), TextureClass::CollideOnly)]);
+        let options = LightingOptions {
+            mesh: MeshOptions {
+                texture_classes: Some(&classes),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let baked = bake(&map, 0, &options).unwrap();
+        let floor = baked
+            .atlas
+            .rects
+            .iter()
+            .find(|r| r.brush == 0 && r.normal == [0.0, 0.0, 1.0])
+            .unwrap();
+        assert!((luxel(&baked, floor, Vec3::ZERO) - 100.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_detail_brushes_cast_shadows() {
+        let mut map = from_str(ROOM).unwrap();
+        let detail = Entity {
+            properties: HashMap::from([("classname".to_string(), "func_detail".to_string())]),
+            brushes: vec![map.entities[0].brushes.pop().unwrap()],
+        };
+        map.entities.push(detail);
+        let baked = bake(&map, 0, &LightingOptions::default()).unwrap();
+
+        let floor = baked
+            .atlas
+            .rects
+            .iter()
+            .find(|r| r.normal == [0.0, 0.0, 1.0])
+            .unwrap();
+        assert_eq!(luxel(&baked, floor, Vec3::ZERO), 0.0);
+    }
+
+    #[test]
+    fn test_broken_lights_are_skipped() {
+        let map = from_str(&format!(
+            r#"{}
+{{
+"classname" "light"
+"light" "100"
+}}
+{{
+"classname" "light"
+"origin" "0 0 32"
+"delay" "9"
+}}"#,
+            ROOM
+        ))
+        .unwrap();
+
+        let lights = Lights::from_map(&map);
+        assert_eq!(lights.lights.len(), 1);
+        assert_eq!(
+            lights.skipped,
+            vec![
+                (2, "light has no origin".to_string()),
+                (3, "unsupported delay 9".to_string())
+            ]
+        );
+
+        let baked = bake(&map, 0, &LightingOptions::default()).unwrap();
+        assert_eq!(baked.skipped_lights, lights.skipped);
+    }
+
+    #[test]
+    fn test_light_from_entity() {
+        let map = from_str(
+            r#"{
+"classname" "light_spot"
+"origin" "0 0 128"
+"light" "150"
+"delay" "2"
+"wait" "0.5"
+"_color" "255 128 0"
+"mangle" "0 -90 0"
+"_cone" "60"
+}"#,
+        )
+        .unwrap();
+        let light = Light::from_entity(&map.entities[0]).unwrap();
+
+        assert_eq!(light.falloff, Falloff::InverseSquare);
+        assert_eq!(light.color, [1.0, 128.0 / 255.0, 0.0]);
+        assert_eq!(light.level(256.0), 150.0);
+
+        let spot = light.spot.unwrap();
+        assert!(Vec3::from_array(spot.direction).distance(Vec3::NEG_Z) < 1e-6);
+        assert_eq!(light.spot_factor(Vec3::NEG_Z), 1.0);
+        assert_eq!(light.spot_factor(Vec3::X), 0.0);
+    }
+
+    #[test]
+    fn test_light_angle_and_delay() {
+        let map = from_str(
+            r#"{
+"classname" "light"
+"origin" "0 0 0"
+"angle" "90"
+"delay" "4"
+}
+{
+"classname" "light_spot"
+"origin" "0 0 0"
+"mangle" "0 -90 0"
+"angle" "30"
+}"#,
+        )
+        .unwrap();
+
+        // `angle` alone doesn't make a spotlight
+        let light = Light::from_entity(&map.entities[0]).unwrap();
+        assert_eq!(light.falloff, Falloff::None);
+        assert_eq!(light.spot, None);
+
+        let spot = Light::from_entity(&map.entities[1]).unwrap().spot.unwrap();
+        assert_eq!(spot.cone, 30.0);
+    }
+
+    #[test]
+    fn test_spot_default_cone() {
+        let map = from_str(
+            r#"{
+"classname" "light_spot"
+"origin" "0 0 0"
+"mangle" "0 -90 0"
+}"#,
+        )
+        .unwrap();
+
+        let light = Light::from_entity(&map.entities[0]).unwrap();
+        assert_eq!(light.spot.unwrap().cone, 40.0);
+        assert!(light.spot_factor(Vec3::new(0.0, 0.3, -1.0).normalize()) > 0.0);
+        assert_eq!(
+            light.spot_factor(Vec3::new(0.0, 0.4, -1.0).normalize()),
+            0.0
+        );
+    }
+
+    #[test]
+    fn test_falloff() {
+        let light = |falloff| Light {
+            origin: [0.0; 3],
+            light: 200.0,
+            color: [1.0; 3],
+            wait: 1.0,
+            falloff,
+            angle_scale: 0.5,
+            spot: None,
+        };
+
+        assert_eq!(light(Falloff::Linear).level(50.0), 150.0);
+        assert_eq!(light(Falloff::Linear).level(500.0), 0.0);
+        assert_eq!(light(Falloff::Inverse).level(256.0), 100.0);
+        assert_eq!(light(Falloff::InverseSquare).level(256.0), 50.0);
+        assert_eq!(light(Falloff::Inverse).level(0.0), 200.0 * 128.0);
+        assert_eq!(
+            light(Falloff::InverseSquare).level(0.0),
+            200.0 * 128.0 * 128.0
+        );
+        assert_eq!(light(Falloff::None).level(10000.0), 200.0);
+        assert_eq!(light(Falloff::InverseSquareClamped).level(0.0), 200.0);
+        assert_eq!(light(Falloff::InverseSquareClamped).level(128.0), 50.0);
+        assert_eq!(light(Falloff::InverseSquareClamped).level(384.0), 12.5);
+    }
+}
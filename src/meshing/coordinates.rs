@@ -0,0 +1,184 @@
+use super::Mesh;
+use glam::Vec3;
+
+/// Which axis points up in the mesh output.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum UpAxis {
+    /// Like the map itself.
+    #[default]
+    Z,
+    /// Like Bevy and glTF. The map's Z becomes Y, and its Y becomes -Z.
+    Y,
+}
+
+/// Coordinate system of the mesh output. Maps are Z-up and right-handed, in Quake units.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CoordinateSystem {
+    pub up: UpAxis,
+    /// Mirrors the axis that's neither up nor X, and reverses the triangle winding to match.
+    pub left_handed: bool,
+    /// Uniform scale applied to positions, e.g. `1.0 / 32.0` for roughly meters.
+    pub scale: f32,
+}
+
+impl CoordinateSystem {
+    /// The coordinate system of the map.
+    pub const MAP: Self = Self {
+        up: UpAxis::Z,
+        left_handed: false,
+        scale: 1.0,
+    };
+
+    /// Y-up and right-handed like Bevy and glTF, without scaling.
+    pub const Y_UP: Self = Self {
+        up: UpAxis::Y,
+        left_handed: false,
+        scale: 1.0,
+    };
+
+    /// Converts a point from map coordinates, scaling it.
+    pub fn point(&self, point: [f32; 3]) -> [f32; 3] {
+        (self.axes(Vec3::from_array(point)) * self.scale).to_array()
+    }
+
+    /// Converts a direction from map coordinates, without scaling it.
+    pub fn direction(&self, direction: [f32; 3]) -> [f32; 3] {
+        self.axes(Vec3::from_array(direction)).to_array()
+    }
+
+    /// Converts a point back into map coordinates, undoing [CoordinateSystem::point].
+    pub fn point_to_map(&self, point: [f32; 3]) -> [f32; 3] {
+        let v = Vec3::from_array(point) / self.scale;
+        let v = match (self.up, self.left_handed) {
+            (UpAxis::Z, false) => v,
+            (UpAxis::Z, true) => Vec3::new(v.x, -v.y, v.z),
+            (UpAxis::Y, false) => Vec3::new(v.x, -v.z, v.y),
+            (UpAxis::Y, true) => Vec3::new(v.x, v.z, v.y),
+        };
+        v.to_array()
+    }
+
+    /// Whether triangles have to be wound the other way after converting.
+    pub fn flips_winding(&self) -> bool {
+        self.left_handed
+    }
+
+    fn axes(&self, v: Vec3) -> Vec3 {
+        match (self.up, self.left_handed) {
+            (UpAxis::Z, false) => v,
+            (UpAxis::Z, true) => Vec3::new(v.x, -v.y, v.z),
+            (UpAxis::Y, false) => Vec3::new(v.x, v.z, -v.y),
+            (UpAxis::Y, true) => Vec3::new(v.x, v.z, v.y),
+        }
+    }
+}
+
+impl Default for CoordinateSystem {
+    fn default() -> Self {
+        Self::MAP
+    }
+}
+
+impl Mesh {
+    /// Converts the mesh from map coordinates into `system`.
+    pub fn convert_coordinates(&mut self, system: &CoordinateSystem) {
+        if *system == CoordinateSystem::MAP {
+            return;
+        }
+
+        for position in &mut self.positions {
+            *position = system.point(*position);
+        }
+        for normal in &mut self.normals {
+            *normal = system.direction(*normal);
+        }
+        for tangent in &mut self.tangents {
+            let [x, y, z, w] = *tangent;
+            let [x, y, z] = system.direction([x, y, z]);
+            // mirroring flips the bitangent along with the winding
+            let w = if system.flips_winding() { -w } else { w };
+            *tangent = [x, y, z, w];
+        }
+        if system.flips_winding() {
+            for tri in self.indices.chunks_exact_mut(3) {
+                tri.swap(1, 2);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{from_str, meshing::MeshOptions, meshing::ToMesh};
+
+    #[test]
+    fn test_coordinate_system() {
+        let system = CoordinateSystem {
+            scale: 0.5,
+            ..CoordinateSystem::Y_UP
+        };
+        assert_eq!(system.point([2.0, 4.0, 6.0]), [1.0, 3.0, -2.0]);
+        assert_eq!(system.direction([0.0, 0.0, 1.0]), [0.0, 1.0, 0.0]);
+        assert_eq!(system.point_to_map([1.0, 3.0, -2.0]), [2.0, 4.0, 6.0]);
+
+        let system = CoordinateSystem {
+            left_handed: true,
+            ..CoordinateSystem::Y_UP
+        };
+        assert_eq!(system.point([2.0, 4.0, 6.0]), [2.0, 6.0, 4.0]);
+        assert_eq!(system.point_to_map([2.0, 6.0, 4.0]), [2.0, 4.0, 6.0]);
+        assert!(system.flips_winding());
+    }
+
+    #[test]
+    fn test_mesh_coordinates() {
+        let map = from_str(include_str!("../../examples/basic.map")).unwrap();
+        let original = map.entities[0].to_mesh().unwrap();
+
+        for left_handed in [false, true] {
+            let system = CoordinateSystem {
+                up: UpAxis::Y,
+                left_handed,
+                scale: 1.0 / 32.0,
+            };
+            let options = MeshOptions {
+                coordinates: system,
+                ..Default::default()
+            };
+            let mesh = map.entities[0].to_mesh_with(&options).unwrap();
+            assert!(mesh
+                .positions
+                .iter()
+                .all(|p| p.iter().all(|c| c.abs() == 2.0)));
+
+            // triangles still face the way their normals point
+            for (tri, original_tri) in mesh
+                .indices
+                .chunks_exact(3)
+                .zip(original.indices.chunks_exact(3))
+            {
+                let [a, b, c] =
+                    [0, 1, 2].map(|n| Vec3::from_array(mesh.positions[tri[n] as usize]));
+                let normal = Vec3::from_array(mesh.normals[tri[0] as usize]);
+                let [oa, ob, oc] = [0, 1, 2]
+                    .map(|n| Vec3::from_array(original.positions[original_tri[n] as usize]));
+                let original_normal = Vec3::from_array(original.normals[original_tri[0] as usize]);
+                assert_eq!(
+                    (b - a).cross(c - a).dot(normal) > 0.0,
+                    (ob - oa).cross(oc - oa).dot(original_normal) > 0.0
+                );
+            }
+
+            // and the bitangents are converted like the other directions
+            let bitangent = |mesh: &Mesh, i: usize| {
+                let [x, y, z, w] = mesh.tangents[i];
+                Vec3::from_array(mesh.normals[i]).cross(Vec3::new(x, y, z)) * w
+            };
+            for i in 0..mesh.positions.len() {
+                let expected = system.direction(bitangent(&original, i).to_array());
+                assert!(bitangent(&mesh, i).distance(Vec3::from_array(expected)) < 1e-5);
+            }
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use super::poly::Poly;
+use glam::{Vec2, Vec3};
+
+/// Settings for generating lightmap UVs and packing them into an atlas.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightmapOptions {
+    /// World units covered by one luxel, Quake uses 16.
+    pub luxel_size: f32,
+    /// Luxels left empty around each face, so filtering doesn't bleed between faces.
+    pub padding: u32,
+}
+
+impl Default for LightmapOptions {
+    fn default() -> Self {
+        Self {
+            luxel_size: 16.0,
+            padding: 1,
+        }
+    }
+}
+
+/// The part of a [LightmapAtlas] used by one face, and how its luxels map to the world.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LightmapRect {
+    pub brush: usize,
+    pub face: usize,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// World position of the center of the luxel at `x`, `y`.
+    pub origin: [f32; 3],
+    /// World offset from one luxel to the next along the width of the rect.
+    pub step_s: [f32; 3],
+    /// World offset from one luxel to the next along the height of the rect.
+    pub step_t: [f32; 3],
+    pub normal: [f32; 3],
+}
+
+/// Lightmap rects of all faces of an entity packed into one texture.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LightmapAtlas {
+    pub width: u32,
+    pub height: u32,
+    pub rects: Vec<LightmapRect>,
+}
+
+/// Maps world positions on a polygon to its lightmap UVs in the atlas.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct LightmapProjection {
+    /// In-plane axes divided by the luxel size.
+    axis_s: Vec3,
+    axis_t: Vec3,
+    /// Smallest luxel coordinates on the polygon.
+    min: Vec2,
+    /// Position of the rect in the atlas.
+    offset: Vec2,
+    atlas_size: Vec2,
+}
+
+impl LightmapProjection {
+    fn luxel(&self, position: Vec3) -> Vec2 {
+        Vec2::new(position.dot(self.axis_s), position.dot(self.axis_t))
+    }
+
+    pub fn uv(&self, position: Vec3) -> Vec2 {
+        // luxels are sampled at their centers
+        (self.luxel(position) - self.min + self.offset + 0.5) / self.atlas_size
+    }
+}
+
+/// Projects each of `polys` onto its plane aligned to the texture's U axis, packs the resulting
+/// rects into an atlas and stores the projections on the polys for their lightmap UVs.
+pub(crate) fn pack_lightmap(polys: &mut [Poly], options: &LightmapOptions) -> LightmapAtlas {
+    let padding = options.padding;
+
+    let mut projections = polys
+        .iter()
+        .map(|poly| {
+            let s = poly.tangent(poly.normal).truncate();
+            let t = poly.normal.cross(s);
+            let mut projection = LightmapProjection {
+                axis_s: s / options.luxel_size,
+                axis_t: t / options.luxel_size,
+                min: Vec2::ZERO,
+                offset: Vec2::ZERO,
+                atlas_size: Vec2::ONE,
+            };
+
+            let luxels = poly.verts.iter().map(|v| projection.luxel(v.position));
+            let min = luxels
+                .clone()
+                .fold(Vec2::splat(f32::MAX), Vec2::min)
+                .floor();
+            let max = luxels.fold(Vec2::splat(f32::MIN), Vec2::max).ceil();
+            projection.min = min;
+
+            let size = (max - min).as_uvec2() + 1;
+            (projection, size.x, size.y)
+        })
+        .collect::<Vec<_>>();
+
+    let total_area = projections
+        .iter()
+        .map(|(_, w, h)| ((w + padding * 2) * (h + padding * 2)) as f32)
+        .sum::<f32>();
+    let widest = projections
+        .iter()
+        .map(|(_, w, _)| w + padding * 2)
+        .max()
+        .unwrap_or(0);
+    let width = widest.max((total_area.sqrt().ceil() as u32).next_power_of_two());
+
+    // shelf packing, tallest rects first
+    let mut order = (0..projections.len()).collect::<Vec<_>>();
+    order.sort_by_key(|i| std::cmp::Reverse((projections[*i].2, projections[*i].1)));
+
+    let mut positions = vec![(0, 0); projections.len()];
+    let (mut x, mut y, mut shelf_height) = (0, 0, 0);
+    for i in order {
+        let (_, w, h) = projections[i];
+        let (w, h) = (w + padding * 2, h + padding * 2);
+        if x + w > width {
+            x = 0;
+            y += shelf_height;
+            shelf_height = 0;
+        }
+        positions[i] = (x + padding, y + padding);
+        x += w;
+        shelf_height = shelf_height.max(h);
+    }
+    let height = y + shelf_height;
+
+    let mut rects = Vec::with_capacity(polys.len());
+    for ((poly, (projection, w, h)), (x, y)) in
+        polys.iter_mut().zip(projections.iter_mut()).zip(positions)
+    {
+        projection.offset = Vec2::new(x as f32, y as f32);
+        projection.atlas_size = Vec2::new(width as f32, height as f32);
+        poly.lightmap = Some(*projection);
+
+        let step_s = projection.axis_s / projection.axis_s.length_squared();
+        let step_t = projection.axis_t / projection.axis_t.length_squared();
+        let distance = poly.normal.dot(poly.verts[0].position);
+        let origin = step_s * projection.min.x + step_t * projection.min.y + poly.normal * distance;
+
+        rects.push(LightmapRect {
+            brush: poly.brush,
+            face: poly.face,
+            x,
+            y,
+            width: *w,
+            height: *h,
+            origin: origin.to_array(),
+            step_s: step_s.to_array(),
+            step_t: step_t.to_array(),
+            normal: poly.normal.to_array(),
+        });
+    }
+
+    LightmapAtlas {
+        width,
+        height,
+        rects,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{from_str, meshing::Mesh};
+
+    #[test]
+    fn test_pack_lightmap() {
+        let map = from_str(include_str!("../../examples/basic.map")).unwrap();
+        let (mesh, atlas) = Mesh::from_entity_with_lightmap(
+            &map.entities[0],
+            &Default::default(),
+            &LightmapOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(atlas.rects.len(), 6);
+        for (n, a) in atlas.rects.iter().enumerate() {
+            assert!(a.x >= 1 && a.x + a.width < atlas.width);
+            assert!(a.y >= 1 && a.y + a.height < atlas.height);
+            for b in &atlas.rects[n + 1..] {
+                let apart = a.x + a.width < b.x
+                    || b.x + b.width < a.x
+                    || a.y + a.height < b.y
+                    || b.y + b.height < a.y;
+                assert!(apart, "{:?} overlaps {:?}", a, b);
+            }
+        }
+
+        // the face at x = -64 is 128 by 128 units
+        let rect = atlas.rects.iter().find(|r| r.normal == [-1.0, 0.0, 0.0]);
+        let rect = rect.unwrap();
+        assert_eq!((rect.width, rect.height), (9, 9));
+        assert_eq!(rect.step_s, [0.0, -16.0, 0.0]);
+        assert_eq!(rect.origin[0], -64.0);
+
+        let uvs = mesh.uvs_lightmap.unwrap();
+        assert_eq!(uvs.len(), mesh.positions.len());
+        for (uv, position) in uvs.iter().zip(&mesh.positions) {
+            assert!(uv[0] > 0.0 && uv[0] < 1.0 && uv[1] > 0.0 && uv[1] < 1.0);
+
+            // each vertex maps back onto its world position
+            let rect = atlas
+                .rects
+                .iter()
+                .find(|r| {
+                    let (x, y) = (uv[0] * atlas.width as f32, uv[1] * atlas.height as f32);
+                    x >= r.x as f32
+                        && x <= (r.x + r.width) as f32
+                        && y >= r.y as f32
+                        && y <= (r.y + r.height) as f32
+                })
+                .unwrap();
+            let s = uv[0] * atlas.width as f32 - rect.x as f32 - 0.5;
+            let t = uv[1] * atlas.height as f32 - rect.y as f32 - 0.5;
+            let world = Vec3::from_array(rect.origin)
+                + Vec3::from_array(rect.step_s) * s
+                + Vec3::from_array(rect.step_t) * t;
+            assert!(world.distance(Vec3::from_array(*position)) < 0.01);
+        }
+
+        assert_eq!(
+            Mesh::from_entity(&map.entities[0]).unwrap().uvs_lightmap,
+            None
+        );
+    }
+}
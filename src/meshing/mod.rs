@@ -1,25 +1,152 @@
+mod coordinates;
+mod lightmap;
+mod occluder;
+mod optimize;
+mod pivot;
 mod poly;
-mod utils;
-
+mod smoothing;
+#[cfg(feature = "mikktspace")]
+mod tangents;
+mod texture;
+mod tjunction;
+pub(crate) mod utils;
+mod vertex_lighting;
+
+pub use self::coordinates::{CoordinateSystem, UpAxis};
+pub use self::lightmap::{LightmapAtlas, LightmapOptions, LightmapRect};
+pub use self::occluder::seals;
+pub use self::smoothing::Smoothing;
+pub use self::texture::{TextureClass, TextureClassifier, TextureSizeProvider};
+pub use self::vertex_lighting::{DirectionalLight, VertexLightingOptions};
+
+pub(crate) use self::occluder::{is_world, Occluder};
+pub(crate) use self::poly::{face_winding, is_bounded};
+
+use self::lightmap::pack_lightmap;
 use self::poly::{Poly, ToPolys};
-use crate::{Brush, Entity, Face};
+use self::smoothing::smooth_normals;
+use self::tjunction::fix_t_junctions;
+use crate::{Brush, Entity};
 use anyhow::{anyhow, Result};
-use glam::{Mat3, Vec2, Vec3};
-use std::collections::HashMap;
+use glam::{DVec3, Vec2, Vec3};
+use std::collections::{BTreeMap, HashMap};
 
 pub trait ToMesh {
-    fn to_mesh(&self) -> Result<Mesh>;
+    fn to_mesh_with(&self, options: &MeshOptions) -> Result<Mesh>;
+
+    /// Returns one [Mesh] per texture name, so each can be given its own material. Types that
+    /// don't group their faces by texture return an error.
+    fn to_meshes_by_texture_with(&self, options: &MeshOptions) -> Result<HashMap<String, Mesh>> {
+        let _ = options;
+        Err(anyhow!("meshes by texture are not supported for this type"))
+    }
+
+    fn to_mesh(&self) -> Result<Mesh> {
+        self.to_mesh_with(&MeshOptions::default())
+    }
+
+    fn to_meshes_by_texture(&self) -> Result<HashMap<String, Mesh>> {
+        self.to_meshes_by_texture_with(&MeshOptions::default())
+    }
 }
 
 impl ToMesh for Brush {
-    fn to_mesh(&self) -> Result<Mesh> {
-        Mesh::from_brush(self)
+    fn to_mesh_with(&self, options: &MeshOptions) -> Result<Mesh> {
+        Mesh::from_brush_with(self, options)
+    }
+
+    fn to_meshes_by_texture_with(&self, options: &MeshOptions) -> Result<HashMap<String, Mesh>> {
+        Mesh::from_brush_by_texture_with(self, options)
     }
 }
 
 impl ToMesh for Entity {
-    fn to_mesh(&self) -> Result<Mesh> {
-        Mesh::from_entity(self)
+    fn to_mesh_with(&self, options: &MeshOptions) -> Result<Mesh> {
+        Mesh::from_entity_with(self, options)
+    }
+
+    fn to_meshes_by_texture_with(&self, options: &MeshOptions) -> Result<HashMap<String, Mesh>> {
+        Mesh::from_entity_by_texture_with(self, options)
+    }
+}
+
+/// Settings that control how brushes are turned into a [Mesh].
+#[derive(Clone, Copy)]
+pub struct MeshOptions<'a> {
+    /// Consulted for the size of each texture when normalizing UVs.
+    pub texture_sizes: Option<&'a dyn TextureSizeProvider>,
+    /// Size used for textures that `texture_sizes` doesn't know about.
+    pub fallback_texture_size: [u32; 2],
+    /// Overrides [TextureClass::from_name], which decides which faces are rendered and which
+    /// brushes collide.
+    pub texture_classes: Option<&'a dyn TextureClassifier>,
+    pub epsilons: Epsilons,
+    /// Fails on brushes that don't enclose a volume instead of leaving them out of entity meshes
+    /// and collision hulls. Single brushes always fail, [Brush::validate] tells which brushes of
+    /// a map are left out.
+    pub strict: bool,
+    /// Splits polygon edges at the vertices of neighbouring polygons lying on them, so the mesh
+    /// has no cracks along T-junctions.
+    pub fix_t_junctions: bool,
+    pub smoothing: Smoothing,
+    /// Axes, handedness and scale of the output, the map's own by default.
+    pub coordinates: CoordinateSystem,
+}
+
+impl<'a> MeshOptions<'a> {
+    pub fn with_texture_sizes(texture_sizes: &'a dyn TextureSizeProvider) -> Self {
+        Self {
+            texture_sizes: Some(texture_sizes),
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn texture_size(&self, name: &str) -> Vec2 {
+        let [width, height] = self
+            .texture_sizes
+            .and_then(|sizes| sizes.texture_size(name))
+            .unwrap_or(self.fallback_texture_size);
+        Vec2::new(width as f32, height as f32)
+    }
+
+    /// The class of the texture `name`, from `texture_classes` or else from its name.
+    pub fn texture_class(&self, name: &str) -> TextureClass {
+        self.texture_classes
+            .and_then(|classes| classes.texture_class(name))
+            .unwrap_or_else(|| TextureClass::from_name(name))
+    }
+}
+
+impl Default for MeshOptions<'_> {
+    fn default() -> Self {
+        Self {
+            texture_sizes: None,
+            fallback_texture_size: [64, 64],
+            texture_classes: None,
+            epsilons: Epsilons::default(),
+            strict: false,
+            fix_t_junctions: false,
+            smoothing: Smoothing::default(),
+            coordinates: CoordinateSystem::default(),
+        }
+    }
+}
+
+/// Tolerances used when intersecting brush planes into polygons.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Epsilons {
+    /// Points closer than this to a plane are considered to lie on it.
+    pub plane: f32,
+    /// Vertices closer than this to each other are merged into one.
+    pub vertex: f32,
+}
+
+impl Default for Epsilons {
+    fn default() -> Self {
+        Self {
+            plane: 0.001,
+            vertex: 0.01,
+        }
     }
 }
 
@@ -28,6 +155,12 @@ pub struct Mesh {
     pub positions: Vec<[f32; 3]>,
     pub normals: Vec<[f32; 3]>,
     pub uvs: Vec<[f32; 2]>,
+    /// Tangents along the U texture axis, with the handedness of the V axis in `w`.
+    pub tangents: Vec<[f32; 4]>,
+    /// Lightmap UVs into a [LightmapAtlas], if the mesh was created with one.
+    pub uvs_lightmap: Option<Vec<[f32; 2]>>,
+    /// Vertex colors from [Mesh::bake_vertex_lighting], if they were baked.
+    pub colors: Option<Vec<[f32; 4]>>,
     pub indices: Vec<u32>,
 }
 
@@ -36,70 +169,244 @@ impl Mesh {
         Self::default()
     }
 
+    /// Concatenates the meshes. A channel that any of the meshes leaves empty is left empty in
+    /// the merged mesh, so it never falls out of step with the positions.
     pub fn merge(meshes: Vec<Mesh>) -> Self {
         let mut positions = Vec::new();
-        let mut normals = Vec::new();
-        let mut uvs = Vec::new();
+        let mut normals = Some(Vec::new());
+        let mut uvs = Some(Vec::new());
+        let mut tangents = Some(Vec::new());
+        let mut uvs_lightmap = (!meshes.is_empty()).then(Vec::new);
+        let mut colors = (!meshes.is_empty()).then(Vec::new);
         let mut indices = Vec::new();
 
         for mut mesh in meshes {
             let offset = positions.len() as u32;
+            let count = mesh.positions.len();
             positions.append(&mut mesh.positions);
-            normals.append(&mut mesh.normals);
-            uvs.append(&mut mesh.uvs);
+            append_channel(&mut normals, mesh.normals, count);
+            append_channel(&mut uvs, mesh.uvs, count);
+            append_channel(&mut tangents, mesh.tangents, count);
+            match (&mut uvs_lightmap, mesh.uvs_lightmap) {
+                (Some(all), Some(mut uvs)) => all.append(&mut uvs),
+                _ => uvs_lightmap = None,
+            }
+            match (&mut colors, mesh.colors) {
+                (Some(all), Some(mut mesh_colors)) => all.append(&mut mesh_colors),
+                _ => colors = None,
+            }
             indices.append(&mut mesh.indices.iter().map(|i| *i + offset).collect());
         }
 
         Self {
             positions,
-            normals,
-            uvs,
+            normals: normals.unwrap_or_default(),
+            uvs: uvs.unwrap_or_default(),
+            tangents: tangents.unwrap_or_default(),
+            uvs_lightmap,
+            colors,
             indices,
         }
     }
 
-    fn from_polys(polys: &Vec<Poly>) -> Result<Self> {
+    /// Copies the vertices at `order` into a new mesh without any indices. Channels the mesh
+    /// leaves empty stay empty.
+    pub(crate) fn select_vertices(&self, order: &[usize]) -> Self {
+        fn select<T: Copy>(channel: &[T], order: &[usize]) -> Vec<T> {
+            order
+                .iter()
+                .filter_map(|&i| channel.get(i).copied())
+                .collect()
+        }
+
+        Self {
+            positions: select(&self.positions, order),
+            normals: select(&self.normals, order),
+            uvs: select(&self.uvs, order),
+            tangents: select(&self.tangents, order),
+            uvs_lightmap: self.uvs_lightmap.as_ref().map(|uvs| select(uvs, order)),
+            colors: self.colors.as_ref().map(|colors| select(colors, order)),
+            indices: Vec::new(),
+        }
+    }
+
+    fn from_polys(polys: &[Poly], options: &MeshOptions) -> Result<Self> {
         let meshes = polys
             .iter()
-            .map(|p| p.triangulate())
+            .map(|p| p.triangulate(options.texture_size(&p.texture)))
             .collect::<Result<Vec<_>>>()?;
 
-        Ok(Self::merge(meshes))
+        let mut mesh = Self::merge(meshes);
+        mesh.convert_coordinates(&options.coordinates);
+        Ok(mesh)
+    }
+
+    /// Processes the polygons of a brush or entity before they're triangulated.
+    fn prepare_polys(
+        mut polys: Vec<Poly>,
+        entity: Option<&Entity>,
+        options: &MeshOptions,
+    ) -> Vec<Poly> {
+        polys.retain(|poly| options.texture_class(&poly.texture).is_rendered());
+        if options.fix_t_junctions {
+            fix_t_junctions(&mut polys, options.epsilons.vertex);
+        }
+        if let Some(angle) = options.smoothing.angle(entity) {
+            smooth_normals(&mut polys, angle, options.epsilons.vertex);
+        }
+        polys
+    }
+
+    fn from_polys_by_texture(
+        polys: Vec<Poly>,
+        options: &MeshOptions,
+    ) -> Result<HashMap<String, Self>> {
+        let mut groups: HashMap<String, Vec<Poly>> = HashMap::new();
+        for poly in polys {
+            groups.entry(poly.texture.clone()).or_default().push(poly);
+        }
+
+        groups
+            .into_iter()
+            .map(|(texture, polys)| Ok((texture, Self::from_polys(&polys, options)?)))
+            .collect()
     }
 
     pub fn from_brush(brush: &Brush) -> Result<Self> {
-        let polys = brush.to_polys();
-
-        // let mut map: HashMap<String, Vec<Poly>> = HashMap::new();
-        // for poly in polys {
-        //     let key = poly.texture.clone();
-        //     if let Some(val) = map.get_mut(&key) {
-        //         val.push(poly);
-        //     } else {
-        //         map.insert(key, vec![poly]);
-        //     }
-        // }
+        Self::from_brush_with(brush, &MeshOptions::default())
+    }
 
-        let meshes = polys
-            .iter()
-            .map(|p| p.triangulate())
-            .collect::<Result<Vec<_>>>()?;
+    pub fn from_brush_with(brush: &Brush, options: &MeshOptions) -> Result<Self> {
+        let polys = Self::prepare_polys(brush.to_polys(options)?, None, options);
+        Self::from_polys(&polys, options)
+    }
+
+    /// Meshes `brush` into one [Mesh] per texture name.
+    pub fn from_brush_by_texture(brush: &Brush) -> Result<HashMap<String, Self>> {
+        Self::from_brush_by_texture_with(brush, &MeshOptions::default())
+    }
 
-        Ok(Self::merge(meshes))
+    pub fn from_brush_by_texture_with(
+        brush: &Brush,
+        options: &MeshOptions,
+    ) -> Result<HashMap<String, Self>> {
+        let polys = Self::prepare_polys(brush.to_polys(options)?, None, options);
+        Self::from_polys_by_texture(polys, options)
     }
 
     pub fn from_entity(entity: &Entity) -> Result<Self> {
+        Self::from_entity_with(entity, &MeshOptions::default())
+    }
+
+    pub fn from_entity_with(entity: &Entity, options: &MeshOptions) -> Result<Self> {
+        Self::from_polys(&Self::entity_polys(entity, options)?, options)
+    }
+
+    /// Meshes all brushes of `entity` into one [Mesh] per texture name.
+    pub fn from_entity_by_texture(entity: &Entity) -> Result<HashMap<String, Self>> {
+        Self::from_entity_by_texture_with(entity, &MeshOptions::default())
+    }
+
+    pub fn from_entity_by_texture_with(
+        entity: &Entity,
+        options: &MeshOptions,
+    ) -> Result<HashMap<String, Self>> {
+        Self::from_polys_by_texture(Self::entity_polys(entity, options)?, options)
+    }
+
+    /// Meshes `entity` with lightmap UVs into an atlas packed from all of its faces.
+    pub fn from_entity_with_lightmap(
+        entity: &Entity,
+        options: &MeshOptions,
+        lightmap: &LightmapOptions,
+    ) -> Result<(Self, LightmapAtlas)> {
+        let mut polys = Self::entity_polys(entity, options)?;
+        let atlas = pack_lightmap(&mut polys, lightmap);
+        Ok((Self::from_polys(&polys, options)?, atlas))
+    }
+
+    /// Like [Mesh::from_entity_with_lightmap], but with one [Mesh] per texture name sharing the
+    /// same atlas.
+    pub fn from_entity_by_texture_with_lightmap(
+        entity: &Entity,
+        options: &MeshOptions,
+        lightmap: &LightmapOptions,
+    ) -> Result<(HashMap<String, Self>, LightmapAtlas)> {
+        let mut polys = Self::entity_polys(entity, options)?;
+        let atlas = pack_lightmap(&mut polys, lightmap);
+        Ok((Self::from_polys_by_texture(polys, options)?, atlas))
+    }
+
+    /// Meshes `entity` relative to its [Entity::pivot] and returns the pivot with the mesh, so
+    /// rotating entities turn around the right point. Entities without a pivot are meshed as
+    /// is, with the pivot at the world origin. The pivot is in the same coordinates as the mesh.
+    pub fn from_entity_around_pivot(entity: &Entity) -> Result<(Self, [f32; 3])> {
+        Self::from_entity_around_pivot_with(entity, &MeshOptions::default())
+    }
+
+    pub fn from_entity_around_pivot_with(
+        entity: &Entity,
+        options: &MeshOptions,
+    ) -> Result<(Self, [f32; 3])> {
+        let pivot = entity.pivot_with(&options.epsilons).unwrap_or_default();
+        let pivot = options.coordinates.point(pivot);
+        let mut mesh = Self::from_entity_with(entity, options)?;
+        mesh.translate(-Vec3::from_array(pivot));
+        Ok((mesh, pivot))
+    }
+
+    /// Like [Mesh::from_entity_around_pivot], but with one [Mesh] per texture name.
+    pub fn from_entity_by_texture_around_pivot(
+        entity: &Entity,
+    ) -> Result<(HashMap<String, Self>, [f32; 3])> {
+        Self::from_entity_by_texture_around_pivot_with(entity, &MeshOptions::default())
+    }
+
+    pub fn from_entity_by_texture_around_pivot_with(
+        entity: &Entity,
+        options: &MeshOptions,
+    ) -> Result<(HashMap<String, Self>, [f32; 3])> {
+        let pivot = entity.pivot_with(&options.epsilons).unwrap_or_default();
+        let pivot = options.coordinates.point(pivot);
+        let mut meshes = Self::from_entity_by_texture_with(entity, options)?;
+        for mesh in meshes.values_mut() {
+            mesh.translate(-Vec3::from_array(pivot));
+        }
+        Ok((meshes, pivot))
+    }
+
+    fn translate(&mut self, offset: Vec3) {
+        for position in &mut self.positions {
+            *position = (Vec3::from_array(*position) + offset).to_array();
+        }
+    }
+
+    /// Meshes `entity` into one [Mesh] per brush and texture name, sorted by both.
+    pub(crate) fn from_entity_by_brush_and_texture_with(
+        entity: &Entity,
+        options: &MeshOptions,
+    ) -> Result<Vec<((usize, String), Self)>> {
+        let mut groups: BTreeMap<(usize, String), Vec<Poly>> = BTreeMap::new();
+        for poly in Self::entity_polys(entity, options)? {
+            groups
+                .entry((poly.brush, poly.texture.clone()))
+                .or_default()
+                .push(poly);
+        }
+
+        groups
+            .into_iter()
+            .map(|(key, polys)| Ok((key, Self::from_polys(&polys, options)?)))
+            .collect()
+    }
+
+    fn entity_polys(entity: &Entity, options: &MeshOptions) -> Result<Vec<Poly>> {
         if entity.brushes.is_empty() {
             return Err(anyhow!("entity has no brushes"));
         }
 
-        let meshes = entity
-            .brushes
-            .iter()
-            .map(|b| Self::from_brush(b))
-            .collect::<Result<Vec<_>>>()?;
-
-        Ok(Self::merge(meshes))
+        let polys = entity.to_polys(options)?;
+        Ok(Self::prepare_polys(polys, Some(entity), options))
     }
 }
 
@@ -110,54 +417,60 @@ pub(crate) struct Vert {
     pub uv: Vec2,
 }
 
+/// A plane through `origin` with the unit `normal` pointing to its front.
 #[derive(Debug, Clone)]
-pub(crate) struct Plane {
-    pub origin: Vec3,
-    pub normal: Vec3,
+pub struct Plane {
+    pub origin: DVec3,
+    pub normal: DVec3,
 }
 
 impl Plane {
-    pub fn new(origin: Vec3, normal: Vec3) -> Self {
+    pub fn new(origin: DVec3, normal: DVec3) -> Self {
         Self { origin, normal }
     }
 
-    pub fn from_triangle(points: [[f32; 3]; 3]) -> Self {
-        let p1 = Vec3::from_array(points[0]);
-        let p2 = Vec3::from_array(points[1]);
-        let p3 = Vec3::from_array(points[2]);
-
-        let d1 = (p2 - p1).normalize();
-        let d2 = (p3 - p1).normalize();
+    /// Returns `None` if the points are collinear and don't span a plane.
+    pub fn from_triangle(points: [[f32; 3]; 3]) -> Option<Self> {
+        let p1 = Vec3::from_array(points[0]).as_dvec3();
+        let p2 = Vec3::from_array(points[1]).as_dvec3();
+        let p3 = Vec3::from_array(points[2]).as_dvec3();
 
-        Self::new(p1, d2.cross(d1))
-    }
-}
+        let d1 = p2 - p1;
+        let d2 = p3 - p1;
+        let normal = d2.cross(d1);
 
-pub(crate) fn plane_point_intersect(planes: [&Plane; 3]) -> Option<Vec3> {
-    let x1 = planes[0].origin;
-    let n1 = planes[0].normal;
-    let x2 = planes[1].origin;
-    let n2 = planes[1].normal;
-    let x3 = planes[2].origin;
-    let n3 = planes[2].normal;
+        // sine of the angle between the two edges
+        if !normal.is_finite() || normal.length() <= d1.length() * d2.length() * 1e-9 {
+            return None;
+        }
 
-    let mat = Mat3::from_cols(n1, n2, n3);
-    let det = mat.determinant();
+        Some(Self::new(p1, normal.normalize()))
+    }
 
-    if det == 0.0 {
-        return None;
+    /// Signed distance from the plane to `point`, positive in front of it.
+    pub fn distance(&self, point: DVec3) -> f64 {
+        self.normal.dot(point - self.origin)
     }
 
-    let v1 = x1.dot(n1) * n2.cross(n3);
-    let v2 = x2.dot(n2) * n3.cross(n1);
-    let v3 = x3.dot(n3) * n1.cross(n2);
+    /// Whether both planes face the same way and lie within `epsilon` of each other.
+    pub fn coincides(&self, other: &Plane, epsilon: f64) -> bool {
+        self.normal.dot(other.normal) > 1.0 - 1e-9 && self.distance(other.origin).abs() <= epsilon
+    }
+}
 
-    Some(det.powf(-1.0) * (v1 + v2 + v3))
+/// Appends the values of a channel of one mesh, or drops the channel if the mesh has no value
+/// for some of its vertices.
+fn append_channel<T>(all: &mut Option<Vec<T>>, mut channel: Vec<T>, vertices: usize) {
+    match all {
+        Some(all) if channel.len() == vertices => all.append(&mut channel),
+        _ => *all = None,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::from_str;
 
     #[test]
     fn test_mesh_merge() {
@@ -165,12 +478,18 @@ mod tests {
             positions: vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [2.0, 0.0, 0.0]],
             normals: vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [2.0, 0.0, 0.0]],
             uvs: vec![[0.0, 0.0], [1.0, 0.0], [2.0, 0.0]],
+            tangents: vec![[0.0, 1.0, 0.0, 1.0]; 3],
+            uvs_lightmap: None,
+            colors: None,
             indices: vec![0, 1, 2],
         };
         let mesh2 = Mesh {
             positions: vec![[3.0, 0.0, 0.0], [4.0, 0.0, 0.0], [5.0, 0.0, 0.0]],
             normals: vec![[3.0, 0.0, 0.0], [4.0, 0.0, 0.0], [5.0, 0.0, 0.0]],
             uvs: vec![[3.0, 0.0], [4.0, 0.0], [5.0, 0.0]],
+            tangents: vec![[0.0, 1.0, 0.0, -1.0]; 3],
+            uvs_lightmap: Some(vec![[0.0, 1.0]; 3]),
+            colors: Some(vec![[1.0; 4]; 3]),
             indices: vec![0, 1, 2],
         };
         let merged = Mesh::merge(vec![mesh1, mesh2]);
@@ -202,8 +521,123 @@ mod tests {
                     [4.0, 0.0],
                     [5.0, 0.0]
                 ],
+                tangents: vec![
+                    [0.0, 1.0, 0.0, 1.0],
+                    [0.0, 1.0, 0.0, 1.0],
+                    [0.0, 1.0, 0.0, 1.0],
+                    [0.0, 1.0, 0.0, -1.0],
+                    [0.0, 1.0, 0.0, -1.0],
+                    [0.0, 1.0, 0.0, -1.0]
+                ],
+                uvs_lightmap: None,
+                colors: None,
                 indices: vec![0, 1, 2, 3, 4, 5],
             }
         )
     }
+
+    #[test]
+    fn test_mesh_merge_drops_missing_channels() {
+        let full = || Mesh {
+            positions: vec![[0.0; 3]; 3],
+            normals: vec![[0.0, 0.0, 1.0]; 3],
+            uvs: vec![[0.0; 2]; 3],
+            tangents: vec![[1.0, 0.0, 0.0, 1.0]; 3],
+            indices: vec![0, 1, 2],
+            ..Default::default()
+        };
+        let positions_only = Mesh {
+            positions: vec![[1.0; 3]; 3],
+            indices: vec![0, 1, 2],
+            ..Default::default()
+        };
+
+        let merged = Mesh::merge(vec![full(), positions_only, full()]);
+        assert_eq!(merged.positions.len(), 9);
+        assert!(merged.normals.is_empty() && merged.uvs.is_empty() && merged.tangents.is_empty());
+
+        // meshes without any vertices don't lack anything
+        let merged = Mesh::merge(vec![full(), Mesh::empty(), full()]);
+        assert_eq!(merged.normals.len(), 6);
+        assert_eq!(merged.uvs.len(), 6);
+        assert_eq!(merged.tangents.len(), 6);
+    }
+
+    #[test]
+    fn test_mesh_from_brush_by_texture() {
+        let map = from_str(
+            r#"{
+"classname" "worldspawn"
+{
+( -64 -64 -16 ) ( -64 -63 -16 ) ( -64 -64 -15 ) WALL [ 0 -1 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( -64 -64 -16 ) ( -64 -64 -15 ) ( -63 -64 -16 ) WALL [ 1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( -64 -64 -16 ) ( -63 -64 -16 ) ( -64 -63 -16 ) FLOOR [ -1 0 0 0 ] [ 0 -1 0 0 ] 0 1 1
+( 64 64 16 ) ( 64 65 16 ) ( 65 64 16 ) FLOOR [ 1 0 0 0 ] [ 0 -1 0 0 ] 0 1 1
+( 64 64 16 ) ( 65 64 16 ) ( 64 64 17 ) WALL [ -1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 64 64 16 ) ( 64 64 17 ) ( 64 65 16 ) WALL [ 0 1 0 0 ] [ 0 0 -1 0 ] 0 1 1
+}
+}"#,
+        )
+        .unwrap();
+        let meshes = map.entities[0].to_meshes_by_texture().unwrap();
+
+        assert_eq!(meshes.len(), 2);
+        assert_eq!(meshes["WALL"].indices.len(), 4 * 6);
+        assert_eq!(meshes["FLOOR"].indices.len(), 2 * 6);
+        assert!(meshes["WALL"].normals.iter().all(|n| n[2] == 0.0));
+        assert!(meshes["FLOOR"].normals.iter().all(|n| n[2].abs() == 1.0));
+        assert!(meshes["FLOOR"]
+            .positions
+            .iter()
+            .all(|p| p[2] == -16.0 || p[2] == 16.0));
+    }
+
+    #[test]
+    fn test_mesh_uvs_use_texture_size() {
+        let map = from_str(
+            r#"{
+"classname" "worldspawn"
+{
+( 0 0 0 ) ( 0 1 0 ) ( 0 0 1 ) TALL [ 0 -1 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 0 0 0 ) ( 0 0 1 ) ( 1 0 0 ) TALL [ 1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 0 0 0 ) ( 1 0 0 ) ( 0 1 0 ) TALL [ -1 0 0 0 ] [ 0 -1 0 0 ] 0 1 1
+( 128 256 64 ) ( 128 257 64 ) ( 129 256 64 ) TALL [ 1 0 0 0 ] [ 0 -1 0 0 ] 0 1 1
+( 128 256 64 ) ( 129 256 64 ) ( 128 256 65 ) TALL [ -1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 128 256 64 ) ( 128 256 65 ) ( 128 257 64 ) TALL [ 0 1 0 0 ] [ 0 0 -1 0 ] 0 1 1
+}
+}"#,
+        )
+        .unwrap();
+        let mut sizes = HashMap::new();
+        sizes.insert("TALL".to_string(), [128, 256]);
+        let options = MeshOptions::with_texture_sizes(&sizes);
+        let meshes = map.entities[0].to_meshes_by_texture_with(&options).unwrap();
+
+        // the top face spans exactly one texture repetition on both axes
+        let mesh = &meshes["TALL"];
+        let top = mesh
+            .normals
+            .iter()
+            .zip(&mesh.uvs)
+            .filter(|(n, _)| n[2] == 1.0)
+            .map(|(_, uv)| *uv)
+            .collect::<Vec<_>>();
+        assert!(top.iter().all(|[u, v]| u.abs() <= 1.0 && v.abs() <= 1.0));
+        assert!(top.contains(&[1.0, -1.0]));
+
+        let meshes = map.entities[0].to_meshes_by_texture().unwrap();
+        assert!(meshes["TALL"].uvs.contains(&[2.0, -4.0]));
+    }
+
+    #[test]
+    fn test_mesh_skips_hidden_textures() {
+        let input = include_str!("../../examples/basic.map");
+        let map = from_str(&input.replacen("TECH28", "skip", 1)).unwrap();
+        let meshes = map.entities[0].to_meshes_by_texture().unwrap();
+        assert_eq!(meshes.len(), 1);
+        assert_eq!(meshes["TECH28"].positions.len(), 5 * 4);
+
+        let map = from_str(&input.replace("TECH28", "clip")).unwrap();
+        assert_eq!(map.entities[0].to_mesh().unwrap(), Mesh::empty());
+    }
 }
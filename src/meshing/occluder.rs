@@ -0,0 +1,120 @@
+use super::{face_winding, MeshOptions, Plane, TextureClass};
+use crate::{Brush, Entity, Map};
+use anyhow::{anyhow, Result};
+use glam::{DVec3, Vec3};
+
+/// A brush that blocks rays, with its bounds to reject most of them early.
+pub(crate) struct Occluder {
+    planes: Vec<Plane>,
+    min: DVec3,
+    max: DVec3,
+}
+
+impl Occluder {
+    /// Returns `None` for brushes without any volume and for brushes that don't [seal](seals)
+    /// the map, like clip brushes and triggers, which light passes through.
+    pub fn new(brush: &Brush, options: &MeshOptions) -> Result<Option<Self>> {
+        if !seals(brush, options) {
+            return Ok(None);
+        }
+
+        let planes = brush
+            .faces
+            .iter()
+            .enumerate()
+            .map(|(i, face)| {
+                Plane::from_triangle(face.triangle)
+                    .ok_or_else(|| anyhow!("face {} has a degenerate plane", i))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let points = (0..planes.len())
+            .filter_map(|i| face_winding(&planes, i, &options.epsilons))
+            .flatten()
+            .collect::<Vec<_>>();
+        if points.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            min: points.iter().fold(DVec3::splat(f64::MAX), |a, b| a.min(*b)),
+            max: points.iter().fold(DVec3::splat(f64::MIN), |a, b| a.max(*b)),
+            planes,
+        }))
+    }
+
+    /// Collects the occluding brushes of the entities of `map` for which `filter` returns true,
+    /// see [Occluder::new]. Degenerate brushes are left out.
+    pub fn from_map(
+        map: &Map,
+        options: &MeshOptions,
+        filter: impl Fn(usize, &Entity) -> bool,
+    ) -> Vec<Self> {
+        map.entities
+            .iter()
+            .enumerate()
+            .filter(|(i, entity)| filter(*i, entity))
+            .flat_map(|(_, entity)| &entity.brushes)
+            .filter_map(|brush| Self::new(brush, options).ok().flatten())
+            .collect()
+    }
+
+    /// Whether the segment from `from` to `to` passes through the brush.
+    pub fn blocks(&self, from: Vec3, to: Vec3) -> bool {
+        let (from, to) = (from.as_dvec3(), to.as_dvec3());
+        if from.max(to).cmplt(self.min).any() || from.min(to).cmpgt(self.max).any() {
+            return false;
+        }
+        clip_segment(&self.planes, from, to).is_some()
+    }
+}
+
+/// Whether a brush keeps the void out: it has a visible or sky face and no liquid faces.
+/// Clip brushes, triggers and hints don't seal a map.
+pub fn seals(brush: &Brush, options: &MeshOptions) -> bool {
+    let classes = brush
+        .faces
+        .iter()
+        .map(|face| options.texture_class(&face.texture_name))
+        .collect::<Vec<_>>();
+    !classes.contains(&TextureClass::Liquid)
+        && classes
+            .iter()
+            .any(|class| matches!(class, TextureClass::Render | TextureClass::Sky))
+}
+
+/// Whether `entity` is part of the world that casts shadows: the worldspawn and the entities
+/// that compilers merge into it.
+pub(crate) fn is_world(entity: &Entity) -> bool {
+    matches!(
+        entity.properties.get("classname").map(String::as_str),
+        Some("worldspawn" | "func_group" | "func_detail" | "func_detail_wall")
+    )
+}
+
+/// Clips the segment from `start` to `end` by the planes of a convex brush. Returns the range of
+/// the segment within the brush as fractions of its length, or `None` if it misses the brush.
+pub(crate) fn clip_segment(planes: &[Plane], start: DVec3, end: DVec3) -> Option<(f64, f64)> {
+    let (mut enter, mut exit) = (0.0, 1.0);
+    for plane in planes {
+        let d_start = plane.distance(start);
+        let d_end = plane.distance(end);
+        if d_start > 0.0 && d_end > 0.0 {
+            return None;
+        }
+        if d_start <= 0.0 && d_end <= 0.0 {
+            continue;
+        }
+
+        let t = d_start / (d_start - d_end);
+        if d_start > 0.0 {
+            enter = t.max(enter);
+        } else {
+            exit = t.min(exit);
+        }
+        if enter >= exit {
+            return None;
+        }
+    }
+    Some((enter, exit))
+}
@@ -0,0 +1,332 @@
+use super::Mesh;
+use glam::Vec3;
+use std::collections::HashMap;
+
+/// Number of entries of the simulated post-transform vertex cache.
+const CACHE_SIZE: usize = 32;
+
+impl Mesh {
+    /// Merges vertices whose attributes are all within `tolerance` of each other,
+    /// so the polygons of the mesh share vertices. Triangles that collapse are removed.
+    pub fn weld(&mut self, tolerance: f32) {
+        let cell_size = tolerance.max(f32::EPSILON) * 2.0;
+        let cell = |p: Vec3| (p / cell_size).floor().as_ivec3().to_array();
+        let matches = |i: usize, j: usize| {
+            Vec3::from_array(self.positions[i]).distance(Vec3::from_array(self.positions[j]))
+                <= tolerance
+                && close(&self.normals, i, j, tolerance)
+                && close(&self.uvs, i, j, tolerance)
+                && close(&self.tangents, i, j, tolerance)
+                && self
+                    .uvs_lightmap
+                    .as_ref()
+                    .is_none_or(|uvs| close(uvs, i, j, tolerance))
+                && self
+                    .colors
+                    .as_ref()
+                    .is_none_or(|colors| close(colors, i, j, tolerance))
+        };
+
+        let mut cells: HashMap<[i32; 3], Vec<usize>> = HashMap::new();
+        let mut remap = Vec::with_capacity(self.positions.len());
+        let mut kept = Vec::new();
+
+        for i in 0..self.positions.len() {
+            let [x, y, z] = cell(Vec3::from_array(self.positions[i]));
+            let mut found = None;
+            'search: for dx in -1..=1 {
+                for dy in -1..=1 {
+                    for dz in -1..=1 {
+                        let key = [x + dx, y + dy, z + dz];
+                        for &k in cells.get(&key).into_iter().flatten() {
+                            if matches(kept[k], i) {
+                                found = Some(k);
+                                break 'search;
+                            }
+                        }
+                    }
+                }
+            }
+
+            let index = found.unwrap_or_else(|| {
+                kept.push(i);
+                cells.entry([x, y, z]).or_default().push(kept.len() - 1);
+                kept.len() - 1
+            });
+            remap.push(index as u32);
+        }
+
+        let mut welded = self.select_vertices(&kept);
+        for tri in self.indices.chunks_exact(3) {
+            let [a, b, c] = [tri[0], tri[1], tri[2]].map(|i| remap[i as usize]);
+            if a != b && b != c && c != a {
+                welded.indices.extend([a, b, c]);
+            }
+        }
+
+        *self = welded;
+    }
+
+    /// Reorders the triangles so that consecutive ones reuse recently transformed vertices, then
+    /// reorders the vertices in the order they are first used.
+    pub fn optimize_vertex_cache(&mut self) {
+        let mut indices = reorder_triangles(&self.indices, self.positions.len());
+
+        let mut remap = vec![u32::MAX; self.positions.len()];
+        let mut order = Vec::with_capacity(self.positions.len());
+        for index in indices.iter_mut() {
+            let old = *index as usize;
+            if remap[old] == u32::MAX {
+                remap[old] = order.len() as u32;
+                order.push(old);
+            }
+            *index = remap[old];
+        }
+
+        *self = Self {
+            indices,
+            ..self.select_vertices(&order)
+        };
+    }
+
+    /// Returns the indices as `u16`, or `None` if the mesh has too many vertices for that.
+    pub fn indices_u16(&self) -> Option<Vec<u16>> {
+        if self.positions.len() > u16::MAX as usize + 1 {
+            return None;
+        }
+        Some(self.indices.iter().map(|i| *i as u16).collect())
+    }
+}
+
+/// Whether vertices `i` and `j` are within `tolerance` in `channel`. Channels the mesh leaves
+/// empty don't keep vertices apart.
+fn close<const N: usize>(channel: &[[f32; N]], i: usize, j: usize, tolerance: f32) -> bool {
+    match (channel.get(i), channel.get(j)) {
+        (Some(a), Some(b)) => {
+            let squared = a.iter().zip(b).map(|(a, b)| (a - b) * (a - b)).sum::<f32>();
+            squared.sqrt() <= tolerance
+        }
+        _ => true,
+    }
+}
+
+/// Score of a vertex according to Tom Forsyth's "Linear-Speed Vertex Cache Optimisation".
+fn vertex_score(cache_position: Option<usize>, remaining: usize) -> f32 {
+    if remaining == 0 {
+        return -1.0;
+    }
+
+    let cache_score = match cache_position {
+        None => 0.0,
+        Some(position) if position < 3 => 0.75,
+        Some(position) => (1.0 - (position - 3) as f32 / (CACHE_SIZE - 3) as f32).powf(1.5),
+    };
+
+    cache_score + 2.0 * (remaining as f32).powf(-0.5)
+}
+
+fn reorder_triangles(indices: &[u32], vertex_count: usize) -> Vec<u32> {
+    let triangle_count = indices.len() / 3;
+
+    let mut vertex_triangles = vec![Vec::new(); vertex_count];
+    for (t, tri) in indices.chunks_exact(3).enumerate() {
+        for &v in tri {
+            vertex_triangles[v as usize].push(t);
+        }
+    }
+
+    let mut remaining = vertex_triangles.iter().map(Vec::len).collect::<Vec<_>>();
+    let mut scores = remaining
+        .iter()
+        .map(|r| vertex_score(None, *r))
+        .collect::<Vec<_>>();
+    let triangle_score = |t: usize, scores: &[f32]| -> f32 {
+        indices[t * 3..t * 3 + 3]
+            .iter()
+            .map(|v| scores[*v as usize])
+            .sum()
+    };
+
+    let mut emitted = vec![false; triangle_count];
+    let mut cache: Vec<u32> = Vec::with_capacity(CACHE_SIZE + 3);
+    let mut reordered = Vec::with_capacity(indices.len());
+    let mut next_unemitted = 0;
+
+    let mut best = None;
+    for _ in 0..triangle_count {
+        let t = match best {
+            Some(t) => t,
+            None => {
+                // nothing in the cache to continue from, start over at the first triangle left,
+                // which keeps this linear where no triangles share vertices
+                while emitted[next_unemitted] {
+                    next_unemitted += 1;
+                }
+                next_unemitted
+            }
+        };
+
+        emitted[t] = true;
+        let tri = &indices[t * 3..t * 3 + 3];
+        reordered.extend_from_slice(tri);
+
+        for &v in tri {
+            remaining[v as usize] -= 1;
+            cache.retain(|c| *c != v);
+        }
+        for &v in tri.iter().rev() {
+            cache.insert(0, v);
+        }
+        let evicted = cache.split_off(cache.len().min(CACHE_SIZE));
+
+        for (position, &v) in cache.iter().enumerate() {
+            scores[v as usize] = vertex_score(Some(position), remaining[v as usize]);
+        }
+        for &v in &evicted {
+            scores[v as usize] = vertex_score(None, remaining[v as usize]);
+        }
+
+        best = cache
+            .iter()
+            .flat_map(|v| vertex_triangles[*v as usize].iter().copied())
+            .filter(|t| !emitted[*t])
+            .max_by(|a, b| triangle_score(*a, &scores).total_cmp(&triangle_score(*b, &scores)));
+    }
+
+    reordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{from_str, meshing::ToMesh};
+
+    fn quads() -> Mesh {
+        // two quads sharing an edge at x = 1
+        Mesh {
+            positions: vec![
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [1.0, 1.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [2.0, 0.0, 0.0],
+                [2.0, 1.0, 0.0],
+                [1.0, 1.0, 0.0001],
+            ],
+            normals: vec![[0.0, 0.0, 1.0]; 8],
+            tangents: vec![[1.0, 0.0, 0.0, 1.0]; 8],
+            uvs: vec![
+                [0.0, 0.0],
+                [1.0, 0.0],
+                [1.0, 1.0],
+                [0.0, 1.0],
+                [1.0, 0.0],
+                [2.0, 0.0],
+                [2.0, 1.0],
+                [1.0, 1.0],
+            ],
+            uvs_lightmap: None,
+            colors: None,
+            indices: vec![0, 1, 2, 0, 2, 3, 4, 5, 6, 4, 6, 7],
+        }
+    }
+
+    fn triangles(mesh: &Mesh) -> Vec<[[i32; 3]; 3]> {
+        let mut tris = mesh
+            .indices
+            .chunks_exact(3)
+            .map(|tri| {
+                let mut tri = [0, 1, 2].map(|n| {
+                    Vec3::from_array(mesh.positions[tri[n] as usize])
+                        .round()
+                        .as_ivec3()
+                        .to_array()
+                });
+                let first = (0..3).min_by_key(|n| tri[*n]).unwrap();
+                tri.rotate_left(first);
+                tri
+            })
+            .collect::<Vec<_>>();
+        tris.sort();
+        tris
+    }
+
+    #[test]
+    fn test_weld() {
+        let mut mesh = quads();
+        mesh.weld(0.001);
+        assert_eq!(mesh.positions.len(), 6);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 2, 3, 1, 4, 5, 1, 5, 2]);
+        assert_eq!(triangles(&mesh), triangles(&quads()));
+
+        let mut mesh = quads();
+        mesh.uvs[4] = [0.0, 0.0];
+        mesh.weld(0.001);
+        assert_eq!(mesh.positions.len(), 7);
+    }
+
+    #[test]
+    fn test_weld_positions_only() {
+        let mut mesh = Mesh {
+            positions: quads().positions,
+            indices: quads().indices,
+            ..Default::default()
+        };
+        mesh.weld(0.001);
+        assert_eq!(mesh.positions.len(), 6);
+        assert!(mesh.normals.is_empty() && mesh.uvs.is_empty() && mesh.tangents.is_empty());
+
+        mesh.optimize_vertex_cache();
+        assert_eq!(mesh.positions.len(), 6);
+    }
+
+    #[test]
+    fn test_weld_removes_collapsed_triangles() {
+        let mut mesh = quads();
+        mesh.weld(1.5);
+        assert!(mesh.indices.is_empty());
+    }
+
+    #[test]
+    fn test_optimize_vertex_cache() {
+        let map = from_str(include_str!("../../examples/basic.map")).unwrap();
+        let original = map.entities[0].to_mesh().unwrap();
+        let mut mesh = map.entities[0].to_mesh().unwrap();
+        mesh.weld(0.001);
+        mesh.optimize_vertex_cache();
+
+        assert_eq!(mesh.positions.len(), 24);
+        assert_eq!(triangles(&mesh), triangles(&original));
+
+        // vertices are in order of first use
+        let mut max = 0;
+        for i in &mesh.indices {
+            assert!(*i <= max + 1);
+            max = max.max(*i);
+        }
+    }
+
+    #[test]
+    fn test_optimize_unwelded_triangles() {
+        // triangles sharing no vertices are taken in order
+        let indices = (0..30000).collect::<Vec<u32>>();
+        assert_eq!(reorder_triangles(&indices, indices.len()), indices);
+    }
+
+    #[test]
+    fn test_indices_u16() {
+        let mesh = quads();
+        assert_eq!(
+            mesh.indices_u16(),
+            Some(vec![0, 1, 2, 0, 2, 3, 4, 5, 6, 4, 6, 7])
+        );
+
+        let mesh = Mesh {
+            positions: vec![[0.0; 3]; 70000],
+            indices: vec![0, 1, 69999],
+            ..Default::default()
+        };
+        assert_eq!(mesh.indices_u16(), None);
+    }
+}
@@ -0,0 +1,109 @@
+use super::{face_winding, Epsilons, Plane};
+use crate::{Brush, Entity};
+use glam::DVec3;
+
+impl Entity {
+    /// The point a rotating brush entity turns around. That's the center of its brush textured
+    /// `origin` in Half-Life, or its `origin` key in Quake-derived games.
+    pub fn pivot(&self) -> Option<[f32; 3]> {
+        self.pivot_with(&Epsilons::default())
+    }
+
+    pub fn pivot_with(&self, epsilons: &Epsilons) -> Option<[f32; 3]> {
+        let from_brush = self
+            .brushes
+            .iter()
+            .filter(|brush| is_origin_brush(brush))
+            .find_map(|brush| brush_center(brush, epsilons));
+        if from_brush.is_some() {
+            return from_brush;
+        }
+
+        self.vector("origin")
+    }
+}
+
+fn is_origin_brush(brush: &Brush) -> bool {
+    !brush.faces.is_empty()
+        && brush.faces.iter().all(|face| {
+            let name = face.texture_name.rsplit('/').next().unwrap_or_default();
+            name.eq_ignore_ascii_case("origin")
+        })
+}
+
+/// Center of the bounds of `brush`, `None` if it has no volume.
+fn brush_center(brush: &Brush, epsilons: &Epsilons) -> Option<[f32; 3]> {
+    let planes = brush
+        .faces
+        .iter()
+        .map(|face| Plane::from_triangle(face.triangle))
+        .collect::<Option<Vec<_>>>()?;
+    let points = (0..planes.len())
+        .filter_map(|i| face_winding(&planes, i, epsilons))
+        .flatten()
+        .collect::<Vec<_>>();
+    if points.is_empty() {
+        return None;
+    }
+
+    let min = points.iter().fold(DVec3::splat(f64::MAX), |a, b| a.min(*b));
+    let max = points.iter().fold(DVec3::splat(f64::MIN), |a, b| a.max(*b));
+    Some(((min + max) * 0.5).as_vec3().to_array())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{from_str, Mesh};
+
+    const DOOR: &str = r#"{
+"classname" "func_door_rotating"
+{
+( 0 -64 0 ) ( 0 -63 0 ) ( 0 -64 1 ) DOOR [ 0 -1 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 0 -64 0 ) ( 0 -64 1 ) ( 1 -64 0 ) DOOR [ 1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 0 -64 0 ) ( 1 -64 0 ) ( 0 -63 0 ) DOOR [ -1 0 0 0 ] [ 0 -1 0 0 ] 0 1 1
+( 8 0 128 ) ( 8 1 128 ) ( 9 0 128 ) DOOR [ 1 0 0 0 ] [ 0 -1 0 0 ] 0 1 1
+( 8 0 128 ) ( 9 0 128 ) ( 8 0 129 ) DOOR [ -1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 8 0 128 ) ( 8 0 129 ) ( 8 1 128 ) DOOR [ 0 1 0 0 ] [ 0 0 -1 0 ] 0 1 1
+}
+{
+( 0 -64 0 ) ( 0 -63 0 ) ( 0 -64 1 ) ORIGIN [ 0 -1 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 0 -64 0 ) ( 0 -64 1 ) ( 1 -64 0 ) ORIGIN [ 1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 0 -64 0 ) ( 1 -64 0 ) ( 0 -63 0 ) ORIGIN [ -1 0 0 0 ] [ 0 -1 0 0 ] 0 1 1
+( 8 -56 128 ) ( 8 -55 128 ) ( 9 -56 128 ) ORIGIN [ 1 0 0 0 ] [ 0 -1 0 0 ] 0 1 1
+( 8 -56 128 ) ( 9 -56 128 ) ( 8 -56 129 ) ORIGIN [ -1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 8 -56 128 ) ( 8 -56 129 ) ( 8 -55 128 ) ORIGIN [ 0 1 0 0 ] [ 0 0 -1 0 ] 0 1 1
+}
+}"#;
+
+    #[test]
+    fn test_pivot_from_origin_brush() {
+        let map = from_str(DOOR).unwrap();
+        let entity = &map.entities[0];
+        assert_eq!(entity.pivot(), Some([4.0, -60.0, 64.0]));
+
+        let (mesh, pivot) = Mesh::from_entity_around_pivot(entity).unwrap();
+        assert_eq!(pivot, [4.0, -60.0, 64.0]);
+
+        // only the door is meshed, relative to the hinge
+        assert_eq!(mesh.positions.len(), 24);
+        assert!(mesh.positions.contains(&[-4.0, -4.0, -64.0]));
+        assert!(mesh.positions.contains(&[4.0, 60.0, 64.0]));
+    }
+
+    #[test]
+    fn test_pivot_from_origin_key() {
+        let input =
+            DOOR.replace("ORIGIN", "DOOR")
+                .replacen("{\n", "{\n\"origin\" \"0 -64 0\"\n", 1);
+        let map = from_str(&input).unwrap();
+        assert_eq!(map.entities[0].pivot(), Some([0.0, -64.0, 0.0]));
+
+        let (meshes, _) = Mesh::from_entity_by_texture_around_pivot(&map.entities[0]).unwrap();
+        assert!(meshes["DOOR"].positions.contains(&[8.0, 64.0, 128.0]));
+
+        let map = from_str(&DOOR.replace("ORIGIN", "DOOR")).unwrap();
+        assert_eq!(map.entities[0].pivot(), None);
+        let (_, pivot) = Mesh::from_entity_around_pivot(&map.entities[0]).unwrap();
+        assert_eq!(pivot, [0.0; 3]);
+    }
+}
@@ -1,59 +1,172 @@
-use super::{plane_point_intersect, Plane, Vert};
+use super::{lightmap::LightmapProjection, Epsilons, MeshOptions, Plane, Vert};
 use crate::{Brush, Entity, Face, Mesh};
 use anyhow::{anyhow, Result};
-use glam::{Vec2, Vec3};
+use glam::{DVec3, Vec2, Vec3, Vec4};
+
+/// Half the extent of the winding every face starts out as before it's clipped by the other
+/// faces of its brush. Anything reaching this far out means the brush isn't closed.
+const BASE_WINDING_SIZE: f64 = 1048576.0;
 
 pub(crate) trait ToPolys {
-    fn to_polys(&self) -> Vec<Poly>;
+    fn to_polys(&self, options: &MeshOptions) -> Result<Vec<Poly>>;
 }
 
 impl ToPolys for Brush {
-    fn to_polys(&self) -> Vec<Poly> {
-        let (planes, mut polys) = self
+    fn to_polys(&self, options: &MeshOptions) -> Result<Vec<Poly>> {
+        let planes = self
             .faces
             .iter()
-            .map(|face| {
-                let plane = Plane::from_triangle(face.triangle);
-                let poly = Poly {
-                    normal: plane.normal,
-                    verts: Vec::new(),
-                    texture: face.texture_name.to_string(),
-                };
-                (plane, poly)
+            .enumerate()
+            .map(|(i, face)| {
+                Plane::from_triangle(face.triangle)
+                    .ok_or_else(|| anyhow!("face {} has a degenerate plane", i))
             })
-            .unzip::<_, _, Vec<_>, Vec<_>>();
-
-        for (i, p1) in planes.iter().enumerate() {
-            for (j, p2) in planes.iter().enumerate() {
-                'inner: for (k, p3) in planes.iter().enumerate() {
-                    if let Some(point) = plane_point_intersect([p1, p2, p3]) {
-                        // all verts must lie in a plane
-                        for p in &planes {
-                            let dist = p.normal.dot(point - p.origin);
-                            if dist > 0.0 {
-                                continue 'inner;
-                            }
-                        }
-
-                        polys[i].add_vert(point, &self.faces[i]);
-                        polys[j].add_vert(point, &self.faces[j]);
-                        polys[k].add_vert(point, &self.faces[k]);
-                    }
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut polys = Vec::new();
+        for (i, face) in self.faces.iter().enumerate() {
+            if let Some(winding) = face_winding(&planes, i, &options.epsilons) {
+                if !is_bounded(&winding) {
+                    return Err(anyhow!("face {} is not bounded by the brush", i));
+                }
+
+                let mut poly = Poly::new(planes[i].normal.as_vec3(), face);
+                poly.face = i;
+                for point in winding {
+                    poly.add_vert(point.as_vec3());
                 }
+                polys.push(poly);
             }
         }
 
-        polys
+        if polys.len() < 4 {
+            return Err(anyhow!("brush does not enclose a volume"));
+        }
+
+        Ok(polys)
     }
 }
 
 impl ToPolys for Entity {
-    fn to_polys(&self) -> Vec<Poly> {
-        self.brushes
-            .iter()
-            .flat_map(|brush| brush.to_polys())
-            .collect()
+    /// Leaves out brushes that don't enclose a volume, unless [MeshOptions::strict] is set.
+    fn to_polys(&self, options: &MeshOptions) -> Result<Vec<Poly>> {
+        let mut polys = Vec::new();
+        for (i, brush) in self.brushes.iter().enumerate() {
+            let brush_polys = match brush.to_polys(options) {
+                Ok(brush_polys) => brush_polys,
+                Err(err) if options.strict => return Err(err.context(format!("brush {}", i))),
+                Err(_) => continue,
+            };
+            polys.extend(
+                brush_polys
+                    .into_iter()
+                    .map(|poly| Poly { brush: i, ..poly }),
+            );
+        }
+        Ok(polys)
+    }
+}
+
+/// Clips a large winding on plane `i` by all other `planes`, leaving the convex polygon the face
+/// contributes to the brush, wound counter-clockwise around its normal. Returns `None` if the
+/// face is redundant.
+pub(crate) fn face_winding(planes: &[Plane], i: usize, epsilons: &Epsilons) -> Option<Vec<DVec3>> {
+    let plane_epsilon = epsilons.plane as f64;
+    let vertex_epsilon = epsilons.vertex as f64;
+
+    let mut winding = base_winding(&planes[i]);
+    for (j, plane) in planes.iter().enumerate() {
+        if j == i {
+            continue;
+        }
+
+        // of several coinciding faces only the first one contributes
+        if planes[i].coincides(plane, plane_epsilon) {
+            if j < i {
+                return None;
+            }
+            continue;
+        }
+
+        winding = clip_winding(&winding, plane, plane_epsilon);
+        if winding.len() < 3 {
+            return None;
+        }
+    }
+
+    winding.dedup_by(|a, b| a.distance(*b) <= vertex_epsilon);
+    while winding.len() > 1 && winding[0].distance(winding[winding.len() - 1]) <= vertex_epsilon {
+        winding.pop();
+    }
+
+    if winding.len() < 3 || winding_area(&winding) <= vertex_epsilon * vertex_epsilon {
+        return None;
     }
+
+    Some(winding)
+}
+
+/// Whether `winding` was closed off by other faces instead of reaching out to the extent of the
+/// base winding.
+pub(crate) fn is_bounded(winding: &[DVec3]) -> bool {
+    winding
+        .iter()
+        .all(|p| p.abs().max_element() < BASE_WINDING_SIZE * 0.5)
+}
+
+fn base_winding(plane: &Plane) -> Vec<DVec3> {
+    let normal = plane.normal;
+    let up = if normal.z.abs() >= normal.x.abs() && normal.z.abs() >= normal.y.abs() {
+        DVec3::X
+    } else {
+        DVec3::Z
+    };
+    let up = (up - normal * up.dot(normal)).normalize() * BASE_WINDING_SIZE;
+    let right = up.cross(normal);
+    let origin = normal * normal.dot(plane.origin);
+
+    vec![
+        origin - right - up,
+        origin + right - up,
+        origin + right + up,
+        origin - right + up,
+    ]
+}
+
+/// Keeps the part of `winding` that lies behind `plane`.
+fn clip_winding(winding: &[DVec3], plane: &Plane, epsilon: f64) -> Vec<DVec3> {
+    let dists = winding
+        .iter()
+        .map(|p| plane.distance(*p))
+        .collect::<Vec<_>>();
+
+    if dists.iter().all(|d| *d <= epsilon) {
+        return winding.to_vec();
+    }
+
+    let mut clipped = Vec::with_capacity(winding.len() + 1);
+    for (n, a) in winding.iter().enumerate() {
+        let m = (n + 1) % winding.len();
+        let (da, db) = (dists[n], dists[m]);
+
+        if da <= epsilon {
+            clipped.push(*a);
+        }
+
+        if (da > epsilon && db < -epsilon) || (da < -epsilon && db > epsilon) {
+            let t = da / (da - db);
+            clipped.push(*a + (winding[m] - *a) * t);
+        }
+    }
+    clipped
+}
+
+fn winding_area(winding: &[DVec3]) -> f64 {
+    let mut sum = DVec3::ZERO;
+    for (n, a) in winding.iter().enumerate() {
+        sum += a.cross(winding[(n + 1) % winding.len()]);
+    }
+    sum.length() * 0.5
 }
 
 #[derive(Debug, Clone)]
@@ -61,27 +174,35 @@ pub(crate) struct Poly {
     pub normal: Vec3,
     pub verts: Vec<Vert>,
     pub texture: String,
+    /// Texture axes divided by the texture scale, a texel step along each is one unit of UV.
+    pub axis_u: Vec3,
+    pub axis_v: Vec3,
+    pub offset: Vec2,
+    /// Indices of the brush within its entity and of the face within the brush.
+    pub brush: usize,
+    pub face: usize,
+    pub lightmap: Option<LightmapProjection>,
 }
 
 impl Poly {
-    pub fn add_vert(&mut self, position: Vec3, face: &Face) {
-        let Face {
-            axis_u,
-            axis_v,
-            offset,
-            scale,
-            ..
-        } = face;
-        let scale = Vec2::from_slice(scale);
-        let axis_u = Vec3::from_slice(axis_u) / scale.x;
-        let axis_v = Vec3::from_slice(axis_v) / scale.y;
-        let offset = Vec2::from_slice(offset);
-
-        let uv = (Vec2::new(
-            position.x * axis_u.x + position.y * axis_u.y + position.z * axis_u.z,
-            position.x * axis_v.x + position.y * axis_v.y + position.z * axis_v.z,
-        ) + offset)
-            / 64.0;
+    pub fn new(normal: Vec3, face: &Face) -> Self {
+        let scale = Vec2::from_array(face.scale);
+        Self {
+            normal,
+            verts: Vec::new(),
+            texture: face.texture_name.to_string(),
+            axis_u: Vec3::from_array(face.axis_u) / scale.x,
+            axis_v: Vec3::from_array(face.axis_v) / scale.y,
+            offset: Vec2::from_array(face.offset),
+            brush: 0,
+            face: 0,
+            lightmap: None,
+        }
+    }
+
+    pub fn add_vert(&mut self, position: Vec3) {
+        // in texels, normalized by the texture size when triangulating
+        let uv = Vec2::new(position.dot(self.axis_u), position.dot(self.axis_v)) + self.offset;
 
         self.verts.push(Vert {
             position,
@@ -90,58 +211,203 @@ impl Poly {
         });
     }
 
-    pub fn ordered_verts(&self) -> Result<Vec<Vert>> {
-        if self.verts.len() < 3 {
-            return Err(anyhow!("not enough points"));
-        }
+    /// Tangent along the direction U increases in, perpendicular to `normal`. The handedness in
+    /// `w` is negative if the direction V increases in is opposite of `normal × tangent`.
+    pub fn tangent(&self, normal: Vec3) -> Vec4 {
+        let tangent = (self.axis_u - normal * normal.dot(self.axis_u))
+            .try_normalize()
+            .unwrap_or_else(|| normal.any_orthonormal_vector());
+        let handedness = if normal.cross(tangent).dot(self.axis_v) < 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        tangent.extend(handedness)
+    }
 
-        let center = self
-            .verts
-            .iter()
-            .fold(Vec3::ZERO, |acc, p| acc + p.position)
-            / self.verts.len() as f32;
-
-        let mut ordered = self.verts.clone();
-        for n in 0..ordered.len() - 2 {
-            let a = (ordered[n].position - center).normalize();
-            let p = self.normal.cross(a);
-
-            let mut smallest_angle = -1.0;
-            let mut smallest = usize::MAX;
-
-            for m in n + 1..ordered.len() {
-                let b = (ordered[m].position - center).normalize();
-                if p.dot(b) > 0.0 {
-                    let angle = a.dot(b);
-                    if angle > smallest_angle {
-                        smallest_angle = angle;
-                        smallest = m;
-                    }
-                }
-            }
+    /// Whether any vertex lies on the straight line between its neighbours.
+    fn has_collinear_verts(&self) -> bool {
+        let len = self.verts.len();
+        (0..len).any(|n| {
+            let a = self.verts[n].position;
+            let b = self.verts[(n + 1) % len].position;
+            let c = self.verts[(n + 2) % len].position;
+            let (ab, bc) = (b - a, c - b);
+            ab.cross(bc).length() <= ab.length() * bc.length() * 1e-4
+        })
+    }
 
-            ordered.swap(n + 1, smallest);
+    pub fn triangulate(&self, texture_size: Vec2) -> Result<Mesh> {
+        if self.verts.len() < 3 {
+            return Err(anyhow!("not enough points"));
         }
 
-        Ok(ordered)
-    }
-
-    pub fn triangulate(&self) -> Result<Mesh> {
-        let verts = self.ordered_verts()?;
+        let mut verts = self.verts.clone();
+        let indices = if self.has_collinear_verts() {
+            // a fan from one of the corners would create degenerate triangles along the edges
+            // with extra vertices on them, so fan from an additional vertex in the center
+            let center = verts.len() as u32;
+            let count = verts.len() as f32;
+            verts.push(Vert {
+                position: verts.iter().fold(Vec3::ZERO, |acc, v| acc + v.position) / count,
+                normal: self.normal,
+                uv: verts.iter().fold(Vec2::ZERO, |acc, v| acc + v.uv) / count,
+            });
+            (0..center)
+                .flat_map(|i| [center, i, (i + 1) % center])
+                .collect()
+        } else {
+            (2..verts.len())
+                .flat_map(|i| [0, (i - 1) as u32, i as u32])
+                .collect()
+        };
 
         let positions = verts.iter().map(|v| v.position.to_array()).collect();
         let normals = verts.iter().map(|v| v.normal.to_array()).collect();
-        let uvs = verts.iter().map(|v| v.uv.to_array()).collect();
-        let indices = (2..verts.len())
-            .flat_map(|i| [0, (i - 1) as u32, i as u32])
+        let uvs = verts
+            .iter()
+            .map(|v| (v.uv / texture_size).to_array())
             .collect();
+        let tangents = verts
+            .iter()
+            .map(|v| self.tangent(v.normal).to_array())
+            .collect();
+        let uvs_lightmap = self.lightmap.map(|lightmap| {
+            verts
+                .iter()
+                .map(|v| lightmap.uv(v.position).to_array())
+                .collect()
+        });
 
         Ok(Mesh {
             positions,
             normals,
             uvs,
+            tangents,
+            uvs_lightmap,
+            colors: None,
             indices,
-            ..Default::default()
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_str;
+    use crate::meshing::utils::CUBE;
+
+    fn brush(faces: &str) -> Brush {
+        let map = from_str(&format!("{{\n{{\n{}\n}}\n}}", faces)).unwrap();
+        map.entities.into_iter().next().unwrap().brushes.remove(0)
+    }
+
+    #[test]
+    fn test_cube_polys() {
+        let polys = brush(CUBE).to_polys(&MeshOptions::default()).unwrap();
+        assert_eq!(polys.len(), 6);
+
+        for poly in polys {
+            assert_eq!(poly.verts.len(), 4);
+            for (n, a) in poly.verts.iter().enumerate() {
+                assert_eq!(a.position.abs(), Vec3::splat(16.0));
+
+                // counter-clockwise around the outward normal
+                let b = &poly.verts[(n + 1) % 4];
+                let c = &poly.verts[(n + 2) % 4];
+                let winding = (b.position - a.position).cross(c.position - b.position);
+                assert!(winding.dot(poly.normal) > 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_redundant_face_is_skipped() {
+        let faces = format!(
+            "{}\n{}\n{}",
+            CUBE,
+            // outside of the cube
+            "( 32 32 32 ) ( 32 33 32 ) ( 33 32 32 ) B [ 1 0 0 0 ] [ 0 -1 0 0 ] 0 1 1",
+            // same plane as the top face
+            "( 0 0 16 ) ( 0 1 16 ) ( 1 0 16 ) C [ 1 0 0 0 ] [ 0 -1 0 0 ] 0 1 1"
+        );
+        let polys = brush(&faces).to_polys(&MeshOptions::default()).unwrap();
+        assert_eq!(polys.len(), 6);
+        assert!(polys.iter().all(|p| p.texture == "A"));
+    }
+
+    #[test]
+    fn test_sliver_within_epsilon_is_ignored() {
+        // bevels the vertical edge at (16, 16) by less than the plane epsilon
+        let faces = format!(
+            "{}\n{}",
+            CUBE,
+            "( 31.9993 0 0 ) ( 31.9993 0 1 ) ( 0 31.9993 0 ) B [ 1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1"
+        );
+        let polys = brush(&faces).to_polys(&MeshOptions::default()).unwrap();
+        assert_eq!(polys.len(), 6);
+        assert!(polys.iter().all(|p| p.verts.len() == 4));
+    }
+
+    #[test]
+    fn test_degenerate_brushes() {
+        let collinear = CUBE.replacen(
+            "( 16 16 16 ) ( 16 17 16 ) ( 17 16 16 )",
+            "( 16 16 16 ) ( 16 17 16 ) ( 16 18 16 )",
+            1,
+        );
+        let err = brush(&collinear)
+            .to_polys(&MeshOptions::default())
+            .unwrap_err();
+        assert_eq!(err.to_string(), "face 3 has a degenerate plane");
+
+        let open = CUBE.lines().take(5).collect::<Vec<_>>().join("\n");
+        let err = brush(&open).to_polys(&MeshOptions::default()).unwrap_err();
+        assert_eq!(err.to_string(), "face 1 is not bounded by the brush");
+
+        let flat = CUBE.replacen(
+            "( 16 16 16 ) ( 16 17 16 ) ( 17 16 16 )",
+            "( 16 16 -16 ) ( 16 17 -16 ) ( 17 16 -16 )",
+            1,
+        );
+        let err = brush(&flat).to_polys(&MeshOptions::default()).unwrap_err();
+        assert_eq!(err.to_string(), "brush does not enclose a volume");
+
+        let entity = Entity {
+            brushes: vec![brush(CUBE), brush(&open)],
+            ..Default::default()
+        };
+        let polys = entity.to_polys(&MeshOptions::default()).unwrap();
+        assert_eq!(polys.len(), 6);
+        assert!(polys.iter().all(|p| p.brush == 0));
+
+        let strict = MeshOptions {
+            strict: true,
+            ..Default::default()
+        };
+        let err = entity.to_polys(&strict).unwrap_err();
+        assert_eq!(err.to_string(), "brush 1");
+    }
+
+    #[test]
+    fn test_tangents() {
+        let polys = brush(CUBE).to_polys(&MeshOptions::default()).unwrap();
+
+        // x = -16 with U along -Y and V along -Z
+        assert_eq!(
+            polys[0].tangent(polys[0].normal),
+            Vec4::new(0.0, -1.0, 0.0, -1.0)
+        );
+        // z = -16 with U along -X and V along -Y
+        assert_eq!(
+            polys[2].tangent(polys[2].normal),
+            Vec4::new(-1.0, 0.0, 0.0, -1.0)
+        );
+
+        // stays perpendicular to a smoothed normal
+        let normal = Vec3::new(-1.0, -1.0, 0.0).normalize();
+        let tangent = polys[0].tangent(normal);
+        assert!(tangent.truncate().dot(normal).abs() < 1e-6);
+        assert!((tangent.truncate().length() - 1.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,220 @@
+use super::poly::Poly;
+use crate::Entity;
+use glam::Vec3;
+use std::collections::HashMap;
+
+/// Smoothing angle ericw-tools uses for `_phong 1` entities without a `_phong_angle`.
+const DEFAULT_PHONG_ANGLE: f32 = 89.0;
+
+/// How vertex normals are computed from the polygons sharing a vertex.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Smoothing {
+    /// Every vertex gets the normal of its own polygon.
+    Flat,
+    /// Entities with `_phong 1` are smoothed by their `_phong_angle`, others are flat.
+    #[default]
+    Entity,
+    /// Normals are averaged across polygons meeting at less than the angle in degrees.
+    Angle(f32),
+}
+
+impl Smoothing {
+    /// The smoothing angle in degrees for `entity`, or `None` if it shouldn't be smoothed.
+    pub fn angle(&self, entity: Option<&Entity>) -> Option<f32> {
+        match self {
+            Self::Flat => None,
+            Self::Angle(angle) => Some(*angle),
+            Self::Entity => {
+                let properties = &entity?.properties;
+                if properties.get("_phong").map(String::as_str) != Some("1") {
+                    return None;
+                }
+                Some(
+                    properties
+                        .get("_phong_angle")
+                        .and_then(|angle| angle.trim().parse().ok())
+                        .unwrap_or(DEFAULT_PHONG_ANGLE),
+                )
+            }
+        }
+    }
+}
+
+/// Averages the normals of vertices within `epsilon` of each other across polygons whose normals
+/// differ by less than `angle` degrees. Each polygon contributes its normal weighted by the angle
+/// of its corner at the vertex.
+pub(crate) fn smooth_normals(polys: &mut [Poly], angle: f32, epsilon: f32) {
+    let threshold = angle.to_radians().cos();
+    let epsilon = epsilon.max(f32::EPSILON);
+    let cell_size = epsilon * 2.0;
+    let cell = |p: Vec3| (p / cell_size).floor().as_ivec3().to_array();
+
+    let mut corners: HashMap<[i32; 3], Vec<(Vec3, Vec3, f32)>> = HashMap::new();
+    for poly in polys.iter() {
+        let len = poly.verts.len();
+        for (n, vert) in poly.verts.iter().enumerate() {
+            let prev = poly.verts[(n + len - 1) % len].position - vert.position;
+            let next = poly.verts[(n + 1) % len].position - vert.position;
+            let weight = prev.angle_between(next);
+            if weight.is_finite() {
+                corners.entry(cell(vert.position)).or_default().push((
+                    vert.position,
+                    poly.normal,
+                    weight,
+                ));
+            }
+        }
+    }
+
+    for poly in polys.iter_mut() {
+        for vert in poly.verts.iter_mut() {
+            let [x, y, z] = cell(vert.position);
+            let mut normal = Vec3::ZERO;
+            for dx in -1..=1 {
+                for dy in -1..=1 {
+                    for dz in -1..=1 {
+                        let key = [x + dx, y + dy, z + dz];
+                        for (position, corner_normal, weight) in
+                            corners.get(&key).into_iter().flatten()
+                        {
+                            if position.distance(vert.position) <= epsilon
+                                && corner_normal.dot(poly.normal) >= threshold
+                            {
+                                normal += *corner_normal * *weight;
+                            }
+                        }
+                    }
+                }
+            }
+            vert.normal = normal.try_normalize().unwrap_or(poly.normal);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        from_str,
+        meshing::{Mesh, MeshOptions},
+    };
+
+    // an octagonal prism, its sides meet at 45 degrees
+    const PIPE: &str = r#"{
+"classname" "func_detail"
+"_phong" "1"
+{
+( 0 0 0 ) ( 0 0 1 ) ( 1 0 0 ) A [ 1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 32 0 0 ) ( 32 0 1 ) ( 64 32 0 ) A [ 1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 64 32 0 ) ( 64 32 1 ) ( 64 64 0 ) A [ 1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 64 64 0 ) ( 64 64 1 ) ( 32 96 0 ) A [ 1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 32 96 0 ) ( 32 96 1 ) ( 0 96 0 ) A [ 1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 0 96 0 ) ( 0 96 1 ) ( -32 64 0 ) A [ 1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( -32 64 0 ) ( -32 64 1 ) ( -32 32 0 ) A [ 1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( -32 32 0 ) ( -32 32 1 ) ( 0 0 0 ) A [ 1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 0 0 0 ) ( 1 0 0 ) ( 0 1 0 ) A [ 1 0 0 0 ] [ 0 -1 0 0 ] 0 1 1
+( 0 0 64 ) ( 0 1 64 ) ( 1 0 64 ) A [ 1 0 0 0 ] [ 0 -1 0 0 ] 0 1 1
+}
+}"#;
+
+    fn side_normals(mesh: &Mesh) -> Vec<Vec3> {
+        mesh.normals
+            .iter()
+            .map(|n| Vec3::from_array(*n))
+            .filter(|n| n.z.abs() < 0.999)
+            .collect()
+    }
+
+    #[test]
+    fn test_smoothing_angle() {
+        let mut map = from_str(PIPE).unwrap();
+        assert_eq!(Smoothing::Entity.angle(map.entities.first()), Some(89.0));
+        assert_eq!(Smoothing::Entity.angle(None), None);
+        assert_eq!(Smoothing::Flat.angle(map.entities.first()), None);
+        assert_eq!(Smoothing::Angle(30.0).angle(None), Some(30.0));
+
+        let properties = &mut map.entities[0].properties;
+        properties.insert("_phong_angle".to_string(), "44".to_string());
+        assert_eq!(Smoothing::Entity.angle(map.entities.first()), Some(44.0));
+    }
+
+    #[test]
+    fn test_smooth_pipe() {
+        let mut map = from_str(PIPE).unwrap();
+        let entity = &map.entities[0];
+
+        let mesh = Mesh::from_entity(entity).unwrap();
+        let normals = side_normals(&mesh);
+        assert_eq!(normals.len(), 8 * 4);
+        for normal in &normals {
+            // horizontal, halfway between two sides
+            assert!(normal.z.abs() < 1e-6);
+            assert!(
+                (normal.x.abs() - (22.5f32).to_radians().cos()).abs() < 1e-4
+                    || (normal.y.abs() - (22.5f32).to_radians().cos()).abs() < 1e-4
+            );
+        }
+
+        // the caps are perpendicular to the sides and stay flat
+        assert!(mesh.normals.iter().any(|n| n == &[0.0, 0.0, 1.0]));
+
+        let options = MeshOptions {
+            smoothing: Smoothing::Flat,
+            ..Default::default()
+        };
+        let mesh = Mesh::from_entity_with(entity, &options).unwrap();
+        for normal in side_normals(&mesh) {
+            assert!(
+                normal.x.abs() < 1e-6 || normal.y.abs() < 1e-6 || normal.x.abs() == normal.y.abs()
+            );
+        }
+
+        map.entities[0]
+            .properties
+            .insert("_phong_angle".to_string(), "30".to_string());
+        let smoothed = Mesh::from_entity(&map.entities[0]).unwrap();
+        let flat = Mesh::from_entity_with(&map.entities[0], &options).unwrap();
+        for (a, b) in side_normals(&smoothed).iter().zip(side_normals(&flat)) {
+            assert!(a.distance(b) < 1e-6);
+        }
+    }
+
+    fn poly(normal: Vec3, points: &[[f32; 3]]) -> Poly {
+        let mut poly = Poly::new(normal, &Default::default());
+        for point in points {
+            poly.add_vert(Vec3::from_array(*point));
+        }
+        poly
+    }
+
+    #[test]
+    fn test_smooth_degenerate_corners() {
+        // the repeated vertex has no angle to weight its normal by
+        let mut polys = [poly(
+            Vec3::Z,
+            &[[0.0; 3], [0.0; 3], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+        )];
+        smooth_normals(&mut polys, 89.0, 0.01);
+        assert!(polys[0].verts.iter().all(|vert| vert.normal == Vec3::Z));
+    }
+
+    #[test]
+    fn test_smooth_across_cells() {
+        // the shared edge lies either side of a cell boundary
+        let normal = Vec3::new(1.0, 0.0, 1.0).normalize();
+        let mut polys = [
+            poly(
+                Vec3::Z,
+                &[[-1.0, 0.0, 0.0], [0.0049, 0.0, 0.0], [0.0049, 1.0, 0.0]],
+            ),
+            poly(
+                normal,
+                &[[0.0051, 0.0, 0.0], [1.0, 0.0, -1.0], [0.0051, 1.0, 0.0]],
+            ),
+        ];
+        smooth_normals(&mut polys, 60.0, 0.01);
+        let expected = (Vec3::Z + normal).normalize();
+        assert!(polys[0].verts[1].normal.distance(expected) < 1e-5);
+        assert!(polys[1].verts[0].normal.distance(expected) < 1e-5);
+    }
+}
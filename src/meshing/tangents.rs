@@ -0,0 +1,86 @@
+use super::Mesh;
+use anyhow::{anyhow, Result};
+
+impl Mesh {
+    /// Replaces the tangents derived from the texture axes with ones generated by MikkTSpace,
+    /// matching what other tools bake normal maps against. Mostly useful for smoothed meshes,
+    /// where the two differ. Fails if the mesh has no normals or UVs.
+    pub fn generate_mikktspace_tangents(&mut self) -> Result<()> {
+        let count = self.positions.len();
+        if self.normals.len() != count || self.uvs.len() != count {
+            return Err(anyhow!("tangents need a normal and a UV for every vertex"));
+        }
+        if self.indices.iter().any(|i| *i as usize >= count) {
+            return Err(anyhow!(
+                "the indices refer to vertices the mesh doesn't have"
+            ));
+        }
+
+        self.tangents = vec![[0.0; 4]; self.positions.len()];
+        if !mikktspace::generate_tangents(self) {
+            return Err(anyhow!("failed to generate tangents"));
+        }
+        Ok(())
+    }
+
+    fn vertex(&self, face: usize, vert: usize) -> usize {
+        self.indices[face * 3 + vert] as usize
+    }
+}
+
+impl mikktspace::Geometry for Mesh {
+    fn num_faces(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.positions[self.vertex(face, vert)]
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.normals[self.vertex(face, vert)]
+    }
+
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        self.uvs[self.vertex(face, vert)]
+    }
+
+    fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize) {
+        let index = self.vertex(face, vert);
+        self.tangents[index] = tangent;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{from_str, meshing::ToMesh};
+    use glam::Vec4;
+
+    #[test]
+    fn test_mikktspace_matches_texture_axes() {
+        let map = from_str(include_str!("../../examples/basic.map")).unwrap();
+        let mesh = map.entities[0].to_mesh().unwrap();
+        let mut generated = map.entities[0].to_mesh().unwrap();
+        generated.generate_mikktspace_tangents().unwrap();
+
+        for (a, b) in mesh.tangents.iter().zip(&generated.tangents) {
+            assert!(Vec4::from_array(*a).distance(Vec4::from_array(*b)) < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_mikktspace_needs_normals_and_uvs() {
+        let map = from_str(include_str!("../../examples/basic.map")).unwrap();
+        let mut mesh = map.entities[0].to_mesh().unwrap();
+        mesh.uvs.clear();
+        assert!(mesh.generate_mikktspace_tangents().is_err());
+
+        let mut mesh = map.entities[0].to_mesh().unwrap();
+        mesh.normals.clear();
+        assert!(mesh.generate_mikktspace_tangents().is_err());
+    }
+}
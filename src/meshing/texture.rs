@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+/// Looks up the size in pixels of a texture by its name, so UVs can be normalized against the
+/// real texture dimensions instead of assuming 64x64.
+pub trait TextureSizeProvider {
+    /// Returns `[width, height]` of the texture `name`, or `None` if it is unknown.
+    fn texture_size(&self, name: &str) -> Option<[u32; 2]>;
+}
+
+impl TextureSizeProvider for HashMap<String, [u32; 2]> {
+    fn texture_size(&self, name: &str) -> Option<[u32; 2]> {
+        self.get(name).copied()
+    }
+}
+
+impl<F> TextureSizeProvider for F
+where
+    F: Fn(&str) -> Option<[u32; 2]>,
+{
+    fn texture_size(&self, name: &str) -> Option<[u32; 2]> {
+        self(name)
+    }
+}
+
+/// What a texture name means to the game, which decides whether faces are rendered and whether
+/// brushes collide.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TextureClass {
+    /// A regular texture that is rendered and collides.
+    Render,
+    /// Collides but isn't rendered, like `clip`.
+    CollideOnly,
+    /// Neither rendered nor solid on its own, like `skip`, `trigger` or `hint`.
+    Invisible,
+    /// Rendered but not solid, like `*water`.
+    Liquid,
+    /// Rendered and solid, but drawn as the sky.
+    Sky,
+}
+
+impl TextureClass {
+    /// Quake and Half-Life's meaning of `name`. Names are compared without case and without any
+    /// directory in front of them.
+    pub fn from_name(name: &str) -> Self {
+        let name = name.rsplit('/').next().unwrap_or_default();
+        let name = name.to_ascii_lowercase();
+        match name.as_str() {
+            "clip" | "playerclip" | "monsterclip" | "nodraw" | "null" => Self::CollideOnly,
+            "skip" | "trigger" | "aaatrigger" | "origin" | "hint" | "hintskip" | "__tb_empty" => {
+                Self::Invisible
+            }
+            _ if name.starts_with('*') || name.starts_with('!') => Self::Liquid,
+            _ if name.starts_with("sky") => Self::Sky,
+            _ => Self::Render,
+        }
+    }
+
+    pub fn is_rendered(&self) -> bool {
+        matches!(self, Self::Render | Self::Liquid | Self::Sky)
+    }
+
+    /// Whether the face makes its brush solid. A brush with any liquid face isn't solid either
+    /// way.
+    pub fn is_solid(&self) -> bool {
+        matches!(self, Self::Render | Self::CollideOnly | Self::Sky)
+    }
+}
+
+/// Overrides the [TextureClass] of texture names, for games with their own special textures.
+pub trait TextureClassifier {
+    /// Returns the class of the texture `name`, or `None` to fall back to
+    /// [TextureClass::from_name].
+    fn texture_class(&self, name: &str) -> Option<TextureClass>;
+}
+
+impl TextureClassifier for HashMap<String, TextureClass> {
+    fn texture_class(&self, name: &str) -> Option<TextureClass> {
+        self.get(name).copied()
+    }
+}
+
+impl<F> TextureClassifier for F
+where
+    F: Fn(&str) -> Option<TextureClass>,
+{
+    fn texture_class(&self, name: &str) -> Option<TextureClass> {
+        self(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_map_provider() {
+        let mut sizes = HashMap::new();
+        sizes.insert("TECH28".to_string(), [128, 256]);
+        assert_eq!(sizes.texture_size("TECH28"), Some([128, 256]));
+        assert_eq!(sizes.texture_size("TECH29"), None);
+    }
+
+    #[test]
+    fn test_fn_provider() {
+        let sizes = |name: &str| name.starts_with("sky").then_some([256, 128]);
+        assert_eq!(sizes.texture_size("sky1"), Some([256, 128]));
+        assert_eq!(sizes.texture_size("TECH28"), None);
+    }
+
+    #[test]
+    fn test_texture_class_from_name() {
+        assert_eq!(TextureClass::from_name("TECH28"), TextureClass::Render);
+        assert_eq!(TextureClass::from_name("CLIP"), TextureClass::CollideOnly);
+        assert_eq!(
+            TextureClass::from_name("tools/skip"),
+            TextureClass::Invisible
+        );
+        assert_eq!(
+            TextureClass::from_name("__TB_empty"),
+            TextureClass::Invisible
+        );
+        assert_eq!(TextureClass::from_name("*water1"), TextureClass::Liquid);
+        assert_eq!(TextureClass::from_name("!toxicgrn"), TextureClass::Liquid);
+        assert_eq!(TextureClass::from_name("sky4"), TextureClass::Sky);
+    }
+
+    #[test]
+    fn test_texture_classifier() {
+        let classes = |name: &str| (name == "caulk").then_some(TextureClass::CollideOnly);
+        assert_eq!(
+            classes.texture_class("caulk"),
+            Some(TextureClass::CollideOnly)
+        );
+        assert_eq!(classes.texture_class("skip"), None);
+    }
+}
@@ -0,0 +1,138 @@
+use super::{poly::Poly, Vert};
+use glam::Vec3;
+
+/// Inserts the vertices of `polys` that lie on an edge of another polygon into that edge, so
+/// neighbouring polygons share all vertices along their common edges and don't leave cracks
+/// between them once triangulated.
+pub(crate) fn fix_t_junctions(polys: &mut [Poly], epsilon: f32) {
+    let points = polys
+        .iter()
+        .flat_map(|poly| poly.verts.iter().map(|v| v.position))
+        .collect::<Vec<_>>();
+
+    // the points sorted along each axis, so only those within an edge's bounds need checking
+    let sorted = [0, 1, 2].map(|axis| {
+        let mut sorted = points.clone();
+        sorted.sort_by(|a, b| a[axis].total_cmp(&b[axis]));
+        sorted
+    });
+
+    for poly in polys.iter_mut() {
+        let mut verts = Vec::with_capacity(poly.verts.len());
+        for (n, a) in poly.verts.iter().enumerate() {
+            let b = &poly.verts[(n + 1) % poly.verts.len()];
+            verts.push(a.clone());
+            verts.extend(split_edge(a, b, &sorted, epsilon));
+        }
+        poly.verts = verts;
+    }
+}
+
+/// Vertices to insert between `a` and `b`, in order from `a` to `b`.
+fn split_edge(a: &Vert, b: &Vert, sorted: &[Vec<Vec3>; 3], epsilon: f32) -> Vec<Vert> {
+    let edge = b.position - a.position;
+    let length_squared = edge.length_squared();
+    if length_squared <= epsilon * epsilon {
+        return Vec::new();
+    }
+
+    let extent = edge.abs();
+    let axis = if extent.x <= extent.y && extent.x <= extent.z {
+        0
+    } else if extent.y <= extent.z {
+        1
+    } else {
+        2
+    };
+    let min = a.position[axis].min(b.position[axis]) - epsilon;
+    let max = a.position[axis].max(b.position[axis]) + epsilon;
+    let sorted = &sorted[axis];
+    let start = sorted.partition_point(|p| p[axis] < min);
+    let end = sorted.partition_point(|p| p[axis] <= max);
+
+    let mut splits = Vec::new();
+    for p in &sorted[start..end] {
+        let t = (*p - a.position).dot(edge) / length_squared;
+        if t <= 0.0 || t >= 1.0 {
+            continue;
+        }
+        if p.distance(a.position) <= epsilon || p.distance(b.position) <= epsilon {
+            continue;
+        }
+        if (a.position + edge * t).distance(*p) > epsilon {
+            continue;
+        }
+        splits.push((t, *p));
+    }
+
+    splits.sort_by(|x, y| x.0.total_cmp(&y.0));
+    splits.dedup_by(|x, y| x.1.distance(y.1) <= epsilon);
+
+    splits
+        .into_iter()
+        .map(|(t, position)| Vert {
+            position,
+            normal: a.normal.lerp(b.normal, t),
+            uv: a.uv.lerp(b.uv, t),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        from_str,
+        meshing::{Mesh, MeshOptions},
+    };
+
+    #[test]
+    fn test_fix_t_junctions() {
+        let map = from_str(include_str!("../../examples/tjunction.map")).unwrap();
+        let options = MeshOptions {
+            fix_t_junctions: true,
+            ..Default::default()
+        };
+        let mesh = Mesh::from_entity_with(&map.entities[0], &options).unwrap();
+
+        let positions = mesh
+            .positions
+            .iter()
+            .map(|p| Vec3::from_array(*p))
+            .collect::<Vec<_>>();
+
+        // the corners of the small brush where it touches the edges of the big one
+        for corner in [Vec3::new(128.0, 0.0, 32.0), Vec3::new(128.0, 64.0, 0.0)] {
+            assert!(
+                mesh.positions
+                    .iter()
+                    .filter(|p| Vec3::from_array(**p) == corner)
+                    .count()
+                    >= 4
+            );
+        }
+
+        for tri in mesh.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|n| positions[tri[n] as usize]);
+
+            // no degenerate triangles from fanning across collinear vertices
+            assert!((b - a).cross(c - a).length() > 0.001);
+
+            // no corner lies inside of an edge between corners, unlike the centers of polygons
+            // those have integer coordinates here
+            for (p, q) in [(a, b), (b, c), (c, a)] {
+                if p != p.round() || q != q.round() {
+                    continue;
+                }
+                for r in positions.iter().filter(|r| **r == r.round()) {
+                    let t = (*r - p).dot(q - p) / (q - p).length_squared();
+                    let on_edge = (p + (q - p) * t).distance(*r) < 0.001;
+                    assert!(!(on_edge && t > 0.001 && t < 0.999), "{} on {} {}", r, p, q);
+                }
+            }
+        }
+
+        let cracked = Mesh::from_entity(&map.entities[0]).unwrap();
+        assert!(cracked.positions.len() < mesh.positions.len());
+    }
+}
@@ -36,6 +36,15 @@ fn combs_util(res: &mut Vec<Vec<usize>>, tmp: &mut Vec<usize>, n: usize, left: u
     }
 }
 
+/// The faces of a 32 unit cube around the origin, for tests that break single faces.
+#[cfg(test)]
+pub(crate) const CUBE: &str = r#"( -16 -16 -16 ) ( -16 -15 -16 ) ( -16 -16 -15 ) A [ 0 -1 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( -16 -16 -16 ) ( -16 -16 -15 ) ( -15 -16 -16 ) A [ 1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( -16 -16 -16 ) ( -15 -16 -16 ) ( -16 -15 -16 ) A [ -1 0 0 0 ] [ 0 -1 0 0 ] 0 1 1
+( 16 16 16 ) ( 16 17 16 ) ( 17 16 16 ) A [ 1 0 0 0 ] [ 0 -1 0 0 ] 0 1 1
+( 16 16 16 ) ( 17 16 16 ) ( 16 16 17 ) A [ -1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 16 16 16 ) ( 16 16 17 ) ( 16 17 16 ) A [ 0 1 0 0 ] [ 0 0 -1 0 ] 0 1 1"#;
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,239 @@
+use super::{is_world, Epsilons, MeshOptions, Occluder};
+use crate::{Map, Mesh};
+use anyhow::{anyhow, Result};
+use glam::Vec3;
+
+/// How far vertices are lifted off their surface before tracing, so they don't hit their own
+/// brush.
+const SAMPLE_OFFSET: f32 = 0.5;
+/// Length of the rays traced towards a [DirectionalLight].
+const LIGHT_DISTANCE: f32 = 65536.0;
+
+/// Parallel light shining on the whole map, like a sun.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DirectionalLight {
+    /// Direction the light travels in.
+    pub direction: [f32; 3],
+    pub color: [f32; 3],
+}
+
+/// Options for [Mesh::bake_vertex_lighting].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VertexLightingOptions {
+    /// Number of rays traced per vertex for the ambient occlusion.
+    pub samples: u32,
+    /// Brushes further away than this don't occlude a vertex.
+    pub distance: f32,
+    /// Color of the ambient light, darkened by the ambient occlusion.
+    pub ambient: [f32; 3],
+    pub light: Option<DirectionalLight>,
+    pub epsilons: Epsilons,
+}
+
+impl Default for VertexLightingOptions {
+    fn default() -> Self {
+        Self {
+            samples: 32,
+            distance: 64.0,
+            ambient: [1.0; 3],
+            light: None,
+            epsilons: Epsilons::default(),
+        }
+    }
+}
+
+impl Mesh {
+    /// Raytraces ambient occlusion and the optional [DirectionalLight] against the solid brushes
+    /// of the worldspawn, `func_group`, `func_detail` and `func_detail_wall` entities of `map`
+    /// and stores the result in `colors`. The RGB of each color is the ambient light scaled by
+    /// the occlusion plus the directional light, the alpha is the occlusion alone, from 0 for
+    /// fully occluded to 1 for open. The mesh has to be in map coordinates, so convert it with
+    /// [Mesh::convert_coordinates] only after baking. Fails if the mesh has no normals.
+    pub fn bake_vertex_lighting(
+        &mut self,
+        map: &Map,
+        options: &VertexLightingOptions,
+    ) -> Result<()> {
+        if self.normals.len() != self.positions.len() {
+            return Err(anyhow!("vertex lighting needs a normal for every vertex"));
+        }
+
+        let mesh_options = MeshOptions {
+            epsilons: options.epsilons,
+            ..Default::default()
+        };
+        let occluders = Occluder::from_map(map, &mesh_options, |_, entity| is_world(entity));
+        let blocked = |from: Vec3, to: Vec3| occluders.iter().any(|o| o.blocks(from, to));
+        let directions = hemisphere(options.samples);
+
+        let colors = self
+            .positions
+            .iter()
+            .zip(&self.normals)
+            .map(|(position, normal)| {
+                let normal = Vec3::from_array(*normal).normalize_or_zero();
+                let origin = Vec3::from_array(*position) + normal * SAMPLE_OFFSET;
+                let (tangent, bitangent) = normal.any_orthonormal_pair();
+
+                let open = directions
+                    .iter()
+                    .filter(|d| {
+                        let direction = tangent * d.x + bitangent * d.y + normal * d.z;
+                        !blocked(origin, origin + direction * options.distance)
+                    })
+                    .count();
+                let occlusion = match directions.len() {
+                    0 => 1.0,
+                    len => open as f32 / len as f32,
+                };
+
+                let mut color = Vec3::from_array(options.ambient) * occlusion;
+                if let Some(light) = &options.light {
+                    let to_light = -Vec3::from_array(light.direction).normalize_or_zero();
+                    let cos = normal.dot(to_light);
+                    if cos > 0.0 && !blocked(origin, origin + to_light * LIGHT_DISTANCE) {
+                        color += Vec3::from_array(light.color) * cos;
+                    }
+                }
+                color.extend(occlusion).to_array()
+            })
+            .collect();
+
+        self.colors = Some(colors);
+        Ok(())
+    }
+}
+
+/// `count` directions spread over the hemisphere around +Z, denser towards the pole so that
+/// each is weighted by its cosine.
+fn hemisphere(count: u32) -> Vec<Vec3> {
+    let golden_angle = std::f32::consts::PI * (3.0 - 5f32.sqrt());
+    (0..count)
+        .map(|i| {
+            // evenly spread over the unit disk, then lifted onto the hemisphere
+            let r = ((i as f32 + 0.5) / count as f32).sqrt();
+            let phi = i as f32 * golden_angle;
+            Vec3::new(r * phi.cos(), r * phi.sin(), (1.0 - r * r).max(0.0).sqrt())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{from_str, meshing::ToMesh, Brush, MapBuilder};
+
+    /// A floor with a box standing on it.
+    const ROOM: &str = r#"{
+"classname" "worldspawn"
+{
+( -128 0 0 ) ( -128 1 0 ) ( -128 0 1 ) FLOOR [ 0 1 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 128 0 0 ) ( 128 0 1 ) ( 128 1 0 ) FLOOR [ 0 1 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 0 -128 0 ) ( 0 -128 1 ) ( 1 -128 0 ) FLOOR [ 1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 0 128 0 ) ( 1 128 0 ) ( 0 128 1 ) FLOOR [ 1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 0 0 -16 ) ( 1 0 -16 ) ( 0 1 -16 ) FLOOR [ 1 0 0 0 ] [ 0 -1 0 0 ] 0 1 1
+( 0 0 0 ) ( 0 1 0 ) ( 1 0 0 ) FLOOR [ 1 0 0 0 ] [ 0 -1 0 0 ] 0 1 1
+}
+{
+( -16 0 0 ) ( -16 1 0 ) ( -16 0 1 ) BOX [ 0 1 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 16 0 0 ) ( 16 0 1 ) ( 16 1 0 ) BOX [ 0 1 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 0 -16 0 ) ( 0 -16 1 ) ( 1 -16 0 ) BOX [ 1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 0 16 0 ) ( 1 16 0 ) ( 0 16 1 ) BOX [ 1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1
+( 0 0 0 ) ( 1 0 0 ) ( 0 1 0 ) BOX [ 1 0 0 0 ] [ 0 -1 0 0 ] 0 1 1
+( 0 0 64 ) ( 0 1 64 ) ( 1 0 64 ) BOX [ 1 0 0 0 ] [ 0 -1 0 0 ] 0 1 1
+}
+}"#;
+
+    #[test]
+    fn test_bake_vertex_lighting() {
+        let map = from_str(ROOM).unwrap();
+        let mut mesh = map.entities[0].brushes[1].to_mesh().unwrap();
+        let options = VertexLightingOptions {
+            distance: 32.0,
+            light: Some(DirectionalLight {
+                direction: [0.0, 0.0, -1.0],
+                color: [0.5, 0.5, 0.5],
+            }),
+            ..Default::default()
+        };
+        mesh.bake_vertex_lighting(&map, &options).unwrap();
+
+        let colors = mesh.colors.as_ref().unwrap();
+        assert_eq!(colors.len(), mesh.positions.len());
+        for ((color, position), normal) in colors.iter().zip(&mesh.positions).zip(&mesh.normals) {
+            let occlusion = color[3];
+            if position[2] == 64.0 {
+                // too far above the floor to be occluded
+                assert_eq!(occlusion, 1.0);
+            } else if normal[2] == 0.0 {
+                // the floor covers about half of the hemisphere of the sides
+                assert!(occlusion > 0.35 && occlusion < 0.65, "{}", occlusion);
+            } else {
+                // the bottom faces straight into the floor
+                assert_eq!(occlusion, 0.0);
+            }
+
+            let lit = if normal[2] == 1.0 { 0.5 } else { 0.0 };
+            assert!((color[0] - (occlusion + lit)).abs() < 1e-6);
+        }
+
+        // welding keeps the vertices apart where their colors differ
+        mesh.weld(0.001);
+        assert_eq!(mesh.positions.len(), 24);
+    }
+
+    #[test]
+    fn test_non_solid_brushes_dont_occlude() {
+        let lid = |texture| {
+            MapBuilder::new()
+                .brush(Brush::cuboid([-64.0, -64.0, 72.0], [64.0, 64.0, 80.0], texture).unwrap())
+                .brush(Brush::default())
+                .brush_entity(
+                    "trigger_once",
+                    &[],
+                    vec![Brush::cuboid([-64.0, -64.0, 88.0], [64.0, 64.0, 96.0], "BOX").unwrap()],
+                )
+                .build()
+        };
+        let top = |map: &Map| {
+            let mut mesh = Brush::cuboid([-16.0, -16.0, 0.0], [16.0, 16.0, 64.0], "BOX")
+                .unwrap()
+                .to_mesh()
+                .unwrap();
+            // the degenerate brush is left out instead of failing the bake
+            mesh.bake_vertex_lighting(map, &Default::default()).unwrap();
+            let colors = mesh.colors.unwrap();
+            (0..colors.len())
+                .filter(|i| mesh.positions[*i][2] == 64.0)
+                .map(|i| colors[i][3])
+                .fold(1.0, f32::min)
+        };
+
+        assert_eq!(top(&lid("CLIP")), 1.0);
+        assert!(top(&lid("LID")) < 1.0);
+    }
+
+    #[test]
+    fn test_bake_without_normals() {
+        let map = from_str(ROOM).unwrap();
+        let mut mesh = map.entities[0].brushes[1].to_mesh().unwrap();
+        mesh.normals.clear();
+        assert!(mesh
+            .bake_vertex_lighting(&map, &Default::default())
+            .is_err());
+        assert_eq!(mesh.colors, None);
+    }
+
+    #[test]
+    fn test_hemisphere() {
+        let directions = hemisphere(64);
+        assert_eq!(directions.len(), 64);
+        for d in &directions {
+            assert!((d.length() - 1.0).abs() < 1e-5 && d.z >= 0.0);
+        }
+
+        // the average of cosine weighted directions points up by 2/3
+        let sum = directions.iter().fold(Vec3::ZERO, |a, b| a + *b);
+        assert!((sum / 64.0).distance(Vec3::new(0.0, 0.0, 2.0 / 3.0)) < 0.02);
+    }
+}
@@ -0,0 +1,504 @@
+//! Writes meshes and maps as Wavefront OBJ with a companion MTL file, and reads convex OBJ
+//! objects back as brushes.
+
+use crate::construction::{convex_hull, face_from_points};
+use crate::meshing::{CoordinateSystem, Epsilons, MeshOptions};
+use crate::{Brush, Map, Mesh};
+use anyhow::{anyhow, Context, Result};
+use glam::{DVec3, Vec3};
+use std::collections::{BTreeSet, HashMap};
+
+/// Options for [Map::to_obj_with].
+#[derive(Clone, Copy)]
+pub struct ObjOptions<'a> {
+    /// How brushes are meshed. OBJ is usually Y-up, which the default converts to.
+    pub mesh: MeshOptions<'a>,
+    /// Welds the vertices of each group and shares positions within this distance across the
+    /// whole file. `None` writes every vertex of every triangle on its own.
+    pub weld: Option<f32>,
+    /// Name of the MTL file the OBJ refers to.
+    pub mtl_name: &'a str,
+    /// Extension appended to texture names for the diffuse maps of the materials.
+    pub texture_extension: &'a str,
+}
+
+impl Default for ObjOptions<'_> {
+    fn default() -> Self {
+        Self {
+            mesh: MeshOptions {
+                coordinates: CoordinateSystem::Y_UP,
+                ..Default::default()
+            },
+            weld: None,
+            mtl_name: "map.mtl",
+            texture_extension: "png",
+        }
+    }
+}
+
+/// The contents of an OBJ file and of the MTL file it refers to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Obj {
+    pub obj: String,
+    pub mtl: String,
+}
+
+impl Mesh {
+    /// Writes the mesh as an OBJ without any materials. Texture coordinates are flipped
+    /// vertically, since OBJ's V axis points up.
+    pub fn to_obj(&self) -> String {
+        let mut writer = ObjWriter::new(None);
+        writer.mesh(self);
+        writer.obj
+    }
+}
+
+impl Map {
+    /// Writes the map as an OBJ with an object per entity, a group per brush and a material per
+    /// texture name. Point entities are left out.
+    pub fn to_obj(&self) -> Result<Obj> {
+        self.to_obj_with(&ObjOptions::default())
+    }
+
+    pub fn to_obj_with(&self, options: &ObjOptions) -> Result<Obj> {
+        let mut writer = ObjWriter::new(options.weld);
+        writer
+            .obj
+            .push_str(&format!("mtllib {}\n", options.mtl_name));
+        let mut textures = BTreeSet::new();
+
+        for (i, entity) in self.entities.iter().enumerate() {
+            if entity.brushes.is_empty() {
+                continue;
+            }
+
+            let meshes = Mesh::from_entity_by_brush_and_texture_with(entity, &options.mesh)
+                .with_context(|| format!("entity {}", i))?;
+            match entity.properties.get("classname") {
+                Some(classname) => writer
+                    .obj
+                    .push_str(&format!("o entity{}_{}\n", i, classname)),
+                None => writer.obj.push_str(&format!("o entity{}\n", i)),
+            }
+
+            let mut brush = None;
+            for ((j, texture), mut mesh) in meshes {
+                if brush != Some(j) {
+                    writer.obj.push_str(&format!("g brush{}\n", j));
+                    brush = Some(j);
+                }
+                writer.obj.push_str(&format!("usemtl {}\n", texture));
+                if let Some(tolerance) = options.weld {
+                    mesh.weld(tolerance);
+                }
+                writer.mesh(&mesh);
+                textures.insert(texture);
+            }
+        }
+
+        let mut mtl = String::new();
+        for texture in textures {
+            mtl.push_str(&format!(
+                "newmtl {}\nKd 1 1 1\nmap_Kd {}.{}\n\n",
+                texture, texture, options.texture_extension
+            ));
+        }
+
+        Ok(Obj {
+            obj: writer.obj,
+            mtl,
+        })
+    }
+}
+
+/// Options for [Brush::from_obj_with].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ObjImportOptions<'a> {
+    /// Coordinate system of the OBJ, Y-up like [ObjOptions] writes by default.
+    pub coordinates: CoordinateSystem,
+    /// Texture of faces without a material.
+    pub texture: &'a str,
+    pub epsilons: Epsilons,
+}
+
+impl Default for ObjImportOptions<'_> {
+    fn default() -> Self {
+        Self {
+            coordinates: CoordinateSystem::Y_UP,
+            texture: "__TB_empty",
+            epsilons: Epsilons::default(),
+        }
+    }
+}
+
+impl Brush {
+    /// Reads every object or group of an OBJ as a brush. Faces are textured with their
+    /// material. Objects that aren't convex or have more than 128 distinct vertices are rejected.
+    pub fn from_obj(input: &str) -> Result<Vec<Self>> {
+        Self::from_obj_with(input, &ObjImportOptions::default())
+    }
+
+    pub fn from_obj_with(input: &str, options: &ObjImportOptions) -> Result<Vec<Self>> {
+        let mut vertices = Vec::new();
+        let mut objects: Vec<ObjObject> = Vec::new();
+        let mut material = None;
+
+        for (n, line) in input.lines().enumerate() {
+            let mut words = line.split_whitespace();
+            let context = || format!("line {}", n + 1);
+            match words.next() {
+                Some("v") => {
+                    let coords = words
+                        .take(3)
+                        .map(|w| w.parse::<f32>())
+                        .collect::<Result<Vec<_>, _>>()
+                        .with_context(context)?;
+                    let point: [f32; 3] = coords
+                        .try_into()
+                        .map_err(|_| anyhow!("vertex needs 3 coordinates"))
+                        .with_context(context)?;
+                    let point = options.coordinates.point_to_map(point);
+                    vertices.push(Vec3::from_array(point).as_dvec3());
+                }
+                Some("o") | Some("g") => objects.push(ObjObject {
+                    name: words.collect::<Vec<_>>().join(" "),
+                    faces: Vec::new(),
+                }),
+                Some("usemtl") => material = words.next().map(str::to_string),
+                Some("f") => {
+                    let indices = words
+                        .map(|w| obj_index(w, vertices.len()))
+                        .collect::<Result<Vec<_>>>()
+                        .with_context(context)?;
+                    if objects.is_empty() {
+                        objects.push(ObjObject::default());
+                    }
+                    let object = objects.last_mut().unwrap();
+                    object.faces.push((indices, material.clone()));
+                }
+                _ => {}
+            }
+        }
+
+        objects
+            .iter()
+            .filter(|object| !object.faces.is_empty())
+            .map(|object| {
+                object
+                    .to_brush(&vertices, options)
+                    .with_context(|| format!("object {:?}", object.name))
+            })
+            .collect()
+    }
+}
+
+#[derive(Default)]
+struct ObjObject {
+    name: String,
+    /// Vertex indices and material of each face.
+    faces: Vec<(Vec<usize>, Option<String>)>,
+}
+
+impl ObjObject {
+    fn to_brush(&self, vertices: &[DVec3], options: &ObjImportOptions) -> Result<Brush> {
+        let points = self
+            .faces
+            .iter()
+            .flat_map(|(indices, _)| indices.iter().map(|i| vertices[*i]))
+            .collect::<Vec<_>>();
+        let planes = convex_hull(&points, &options.epsilons)?;
+        let epsilon = options.epsilons.vertex as f64;
+
+        let mut textures = vec![None; planes.len()];
+        for (n, (indices, material)) in self.faces.iter().enumerate() {
+            let face = indices.iter().map(|i| vertices[*i]).collect::<Vec<_>>();
+            let plane = planes.iter().position(|plane| {
+                face.iter()
+                    .all(|p| (plane.normal.dot(*p) - plane.distance).abs() <= epsilon)
+            });
+            let plane = plane.ok_or_else(|| {
+                anyhow!(
+                    "face {} is not on the convex hull, the object is not convex",
+                    n
+                )
+            })?;
+            textures[plane] = textures[plane].or(material.as_deref());
+        }
+
+        Ok(Brush {
+            faces: planes
+                .iter()
+                .zip(textures)
+                .map(|(plane, texture)| {
+                    let texture = texture.unwrap_or(options.texture);
+                    face_from_points(plane.triangle, plane.normal, texture)
+                })
+                .collect(),
+        })
+    }
+}
+
+/// Resolves a face vertex like `3/1/2` or `-1` to a vertex index.
+fn obj_index(word: &str, vertex_count: usize) -> Result<usize> {
+    let index = word.split('/').next().unwrap_or_default();
+    let index = index
+        .parse::<i64>()
+        .with_context(|| format!("invalid vertex {:?}", word))?;
+    let resolved = if index < 0 {
+        vertex_count as i64 + index
+    } else {
+        index - 1
+    };
+    if resolved < 0 || resolved >= vertex_count as i64 {
+        return Err(anyhow!("vertex {} is out of range", index));
+    }
+    Ok(resolved as usize)
+}
+
+struct ObjWriter {
+    obj: String,
+    weld: Option<f32>,
+    /// Indices of positions already written, by their position on a grid of the weld
+    /// tolerance.
+    positions: HashMap<[i64; 3], usize>,
+    position_count: usize,
+    /// Number of texture coordinates written so far, which are never shared.
+    uv_count: usize,
+    /// Number of normals written so far, which are never shared either.
+    normal_count: usize,
+}
+
+impl ObjWriter {
+    fn new(weld: Option<f32>) -> Self {
+        Self {
+            obj: String::new(),
+            weld,
+            positions: HashMap::new(),
+            position_count: 0,
+            uv_count: 0,
+            normal_count: 0,
+        }
+    }
+
+    fn mesh(&mut self, mesh: &Mesh) {
+        let mut position_indices = Vec::with_capacity(mesh.positions.len());
+        for position in &mesh.positions {
+            let key = self.weld.map(|tolerance| {
+                (Vec3::from_array(*position) / tolerance.max(f32::EPSILON))
+                    .round()
+                    .as_dvec3()
+                    .to_array()
+                    .map(|c| c as i64)
+            });
+            let shared = key.and_then(|key| self.positions.get(&key).copied());
+
+            let index = shared.unwrap_or_else(|| {
+                let [x, y, z] = position;
+                self.obj.push_str(&format!("v {} {} {}\n", x, y, z));
+                self.position_count += 1;
+                if let Some(key) = key {
+                    self.positions.insert(key, self.position_count);
+                }
+                self.position_count
+            });
+            position_indices.push(index);
+        }
+
+        // channels the mesh leaves empty are left out of the faces
+        let has_uvs = mesh.uvs.len() == mesh.positions.len();
+        let has_normals = mesh.normals.len() == mesh.positions.len();
+        if has_uvs {
+            for [u, v] in &mesh.uvs {
+                self.obj.push_str(&format!("vt {} {}\n", u, 1.0 - v));
+            }
+        }
+        if has_normals {
+            for [x, y, z] in &mesh.normals {
+                self.obj.push_str(&format!("vn {} {} {}\n", x, y, z));
+            }
+        }
+
+        for tri in mesh.indices.chunks_exact(3) {
+            self.obj.push('f');
+            for &i in tri {
+                let position = position_indices[i as usize];
+                let uv = self.uv_count + i as usize + 1;
+                let normal = self.normal_count + i as usize + 1;
+                let vertex = match (has_uvs, has_normals) {
+                    (true, true) => format!(" {}/{}/{}", position, uv, normal),
+                    (true, false) => format!(" {}/{}", position, uv),
+                    (false, true) => format!(" {}//{}", position, normal),
+                    (false, false) => format!(" {}", position),
+                };
+                self.obj.push_str(&vertex);
+            }
+            self.obj.push('\n');
+        }
+        if has_uvs {
+            self.uv_count += mesh.uvs.len();
+        }
+        if has_normals {
+            self.normal_count += mesh.normals.len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_str;
+
+    fn count(obj: &str, prefix: &str) -> usize {
+        obj.lines().filter(|l| l.starts_with(prefix)).count()
+    }
+
+    #[test]
+    fn test_mesh_to_obj() {
+        let map = from_str(include_str!("../examples/basic.map")).unwrap();
+        let obj = Mesh::from_entity(&map.entities[0]).unwrap().to_obj();
+
+        assert_eq!(count(&obj, "v "), 24);
+        assert_eq!(count(&obj, "vt "), 24);
+        assert_eq!(count(&obj, "vn "), 24);
+        assert_eq!(count(&obj, "f "), 12);
+        assert!(obj.lines().any(|l| l == "f 1/1/1 2/2/2 3/3/3"));
+    }
+
+    #[test]
+    fn test_mesh_without_uvs_or_normals_to_obj() {
+        let map = from_str(include_str!("../examples/basic.map")).unwrap();
+        let mesh = Mesh::from_entity(&map.entities[0]).unwrap();
+
+        let without_uvs = Mesh {
+            uvs: Vec::new(),
+            ..Mesh::from_entity(&map.entities[0]).unwrap()
+        }
+        .to_obj();
+        assert_eq!(count(&without_uvs, "vt "), 0);
+        assert!(without_uvs.lines().any(|l| l == "f 1//1 2//2 3//3"));
+
+        let without_normals = Mesh {
+            normals: Vec::new(),
+            ..Mesh::from_entity(&map.entities[0]).unwrap()
+        }
+        .to_obj();
+        assert_eq!(count(&without_normals, "vn "), 0);
+        assert!(without_normals.lines().any(|l| l == "f 1/1 2/2 3/3"));
+
+        let positions_only = Mesh {
+            positions: mesh.positions,
+            indices: mesh.indices,
+            ..Default::default()
+        }
+        .to_obj();
+        assert!(positions_only.lines().any(|l| l == "f 1 2 3"));
+    }
+
+    #[test]
+    fn test_map_to_obj() {
+        let map = from_str(include_str!("../examples/tjunction.map")).unwrap();
+        let Obj { obj, mtl } = map.to_obj().unwrap();
+
+        assert!(obj.starts_with("mtllib map.mtl\n"));
+        assert_eq!(count(&obj, "o "), 1);
+        assert!(obj.contains("o entity0_worldspawn\n"));
+        assert!(obj.contains("g brush0\n") && obj.contains("g brush1\n"));
+        assert_eq!(count(&obj, "usemtl TECH28"), 2);
+        assert_eq!(mtl, "newmtl TECH28\nKd 1 1 1\nmap_Kd TECH28.png\n\n");
+
+        // faces of later groups refer to the vertices of their own group
+        let vertices = count(&obj, "vt ");
+        let last_face = obj.lines().rfind(|l| l.starts_with("f ")).unwrap();
+        let last_vertex = last_face
+            .split_whitespace()
+            .skip(1)
+            .map(|v| v.split('/').nth(1).unwrap().parse::<usize>().unwrap())
+            .max()
+            .unwrap();
+        assert_eq!(last_vertex, vertices);
+    }
+
+    #[test]
+    fn test_map_to_obj_welded() {
+        let map = from_str(include_str!("../examples/tjunction.map")).unwrap();
+        let options = ObjOptions {
+            weld: Some(0.001),
+            ..Default::default()
+        };
+        let unwelded = map.to_obj().unwrap().obj;
+        let welded = map.to_obj_with(&options).unwrap().obj;
+
+        // the brushes share one corner where they touch
+        assert_eq!(count(&unwelded, "v "), 48);
+        assert_eq!(count(&welded, "v "), 15);
+        assert_eq!(count(&welded, "f "), count(&unwelded, "f "));
+    }
+
+    #[test]
+    fn test_brush_from_obj() {
+        let map = from_str(include_str!("../examples/tjunction.map")).unwrap();
+        let obj = map.to_obj().unwrap().obj;
+        let brushes = Brush::from_obj(&obj.replace("usemtl TECH28\n", "")).unwrap();
+        assert_eq!(brushes.len(), 2);
+
+        for (brush, original) in brushes.iter().zip(&map.entities[0].brushes) {
+            assert_eq!(brush.faces.len(), 6);
+            assert!(brush.faces.iter().all(|f| f.texture_name == "__TB_empty"));
+
+            // the brushes enclose the same corners as the ones they were exported from
+            let corners = |brush: &Brush| {
+                let mut corners = brush.convex_hull().unwrap().vertices;
+                corners.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                corners
+            };
+            assert_eq!(corners(brush), corners(original));
+        }
+    }
+
+    #[test]
+    fn test_brush_from_obj_materials() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0 1\nusemtl A\nf 1 3 2\nusemtl B\nf 1 2 4\nf 1 4 3\nf 2 3 4\n";
+        let options = ObjImportOptions {
+            coordinates: CoordinateSystem::MAP,
+            ..Default::default()
+        };
+        let brushes = Brush::from_obj_with(obj, &options).unwrap();
+        assert_eq!(brushes.len(), 1);
+
+        let bottom = brushes[0]
+            .faces
+            .iter()
+            .find(|f| f.triangle.iter().all(|p| p[2] == 0.0))
+            .unwrap();
+        assert_eq!(bottom.texture_name, "A");
+        assert_eq!(
+            brushes[0]
+                .faces
+                .iter()
+                .filter(|f| f.texture_name == "B")
+                .count(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_brush_from_obj_rejects_concave() {
+        // an L-shaped prism, notched at x > 1, y > 1
+        let mut obj = String::new();
+        for z in [0, 1] {
+            for [x, y] in [[0, 0], [2, 0], [2, 1], [1, 1], [1, 2], [0, 2]] {
+                obj.push_str(&format!("v {} {} {}\n", x, y, z));
+            }
+        }
+        obj.push_str("o L\nf 1 6 5 4 3 2\nf 7 8 9 10 11 12\nf 3 4 10 9\n");
+        let options = ObjImportOptions {
+            coordinates: CoordinateSystem::MAP,
+            ..Default::default()
+        };
+        let error = Brush::from_obj_with(&obj, &options).unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            "object \"L\": face 2 is not on the convex hull, the object is not convex"
+        );
+    }
+}
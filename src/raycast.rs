@@ -0,0 +1,369 @@
+use crate::geometry::face_windings;
+use crate::meshing::{Epsilons, Plane};
+use crate::{Brush, Entity, Face, Map};
+use glam::{DVec3, Vec2, Vec3};
+
+/// Where a ray first enters a brush.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit {
+    /// Distance from the ray origin along its normalized direction.
+    pub distance: f32,
+    pub entity: usize,
+    pub brush: usize,
+    pub face: usize,
+    pub point: [f32; 3],
+    /// Outward normal of the hit face.
+    pub normal: [f32; 3],
+    /// Texture coordinates at the hit in texels, divide them by the texture size to get the UV
+    /// of the mesh.
+    pub uv: [f32; 2],
+}
+
+impl Brush {
+    /// Casts a ray from `origin` along `direction` and returns where it enters the brush. Rays
+    /// starting inside the brush don't hit it, brushes that don't enclose a volume are never
+    /// hit. `entity` and `brush` of the hit are set to 0.
+    pub fn cast_ray(&self, origin: [f32; 3], direction: [f32; 3]) -> Option<RayHit> {
+        let planes = self.closed_planes()?;
+        let (origin, direction) = ray(origin, direction)?;
+        let (distance, face) = clip_ray(&planes, origin, direction)?;
+        let projection = Projection::new(&self.faces[face]);
+        Some(hit(
+            face,
+            &projection,
+            &planes[face],
+            origin,
+            direction,
+            distance,
+        ))
+    }
+}
+
+impl Entity {
+    /// The nearest hit on any brush of the entity, with `entity` set to 0.
+    pub fn cast_ray(&self, origin: [f32; 3], direction: [f32; 3]) -> Option<RayHit> {
+        self.brushes
+            .iter()
+            .enumerate()
+            .filter_map(|(i, brush)| {
+                let hit = brush.cast_ray(origin, direction)?;
+                Some(RayHit { brush: i, ..hit })
+            })
+            .min_by(|a, b| a.distance.total_cmp(&b.distance))
+    }
+}
+
+impl Map {
+    /// The nearest hit on any brush of the map. For many rays against the same map a
+    /// [RayCaster] is faster, and a [crate::MapIndex] for large maps.
+    pub fn cast_ray(&self, origin: [f32; 3], direction: [f32; 3]) -> Option<RayHit> {
+        self.entities
+            .iter()
+            .enumerate()
+            .filter_map(|(i, entity)| {
+                let hit = entity.cast_ray(origin, direction)?;
+                Some(RayHit { entity: i, ..hit })
+            })
+            .min_by(|a, b| a.distance.total_cmp(&b.distance))
+    }
+}
+
+/// Casts rays against the brushes of a map, with their planes prepared and bounds to skip most
+/// of them.
+#[derive(Debug)]
+pub struct RayCaster {
+    brushes: Vec<Target>,
+}
+
+#[derive(Debug)]
+struct Target {
+    entity: usize,
+    brush: usize,
+    planes: Vec<Plane>,
+    projections: Vec<Projection>,
+    min: DVec3,
+    max: DVec3,
+}
+
+impl RayCaster {
+    pub fn new(map: &Map) -> Self {
+        Self::with_filter(map, |_, _| true)
+    }
+
+    /// Only casts against the brushes of the entities for which `filter` returns true. Brushes
+    /// that don't enclose a volume are left out, as [Map::cast_ray] never hits them either.
+    pub fn with_filter(map: &Map, filter: impl Fn(usize, &Entity) -> bool) -> Self {
+        let epsilons = Epsilons::default();
+        let mut brushes = Vec::new();
+        for (i, entity) in map.entities.iter().enumerate() {
+            if !filter(i, entity) {
+                continue;
+            }
+            for (j, brush) in entity.brushes.iter().enumerate() {
+                let planes = match brush.planes() {
+                    Ok(planes) => planes,
+                    Err(_) => continue,
+                };
+                let points = match face_windings(&planes, &epsilons) {
+                    Ok(windings) => windings
+                        .into_iter()
+                        .flat_map(|(_, w)| w)
+                        .collect::<Vec<_>>(),
+                    Err(_) => continue,
+                };
+
+                brushes.push(Target {
+                    entity: i,
+                    brush: j,
+                    min: points.iter().fold(DVec3::splat(f64::MAX), |a, b| a.min(*b)),
+                    max: points.iter().fold(DVec3::splat(f64::MIN), |a, b| a.max(*b)),
+                    projections: brush.faces.iter().map(Projection::new).collect(),
+                    planes,
+                });
+            }
+        }
+        Self { brushes }
+    }
+
+    /// The nearest hit, like [Map::cast_ray]. Every brush's bounds are tested, see
+    /// [crate::MapIndex::cast_ray] for large maps.
+    pub fn cast_ray(&self, origin: [f32; 3], direction: [f32; 3]) -> Option<RayHit> {
+        let (origin, direction) = ray(origin, direction)?;
+
+        // test the brushes in the order the ray reaches their bounds, until the next one starts
+        // beyond the nearest hit
+        let mut candidates = self
+            .brushes
+            .iter()
+            .filter_map(|target| {
+                let enter = enter_box(target.min, target.max, origin, direction)?;
+                Some((enter, target))
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut nearest: Option<RayHit> = None;
+        for (enter, target) in candidates {
+            if nearest.is_some_and(|hit| enter > hit.distance as f64) {
+                break;
+            }
+            if let Some((distance, face)) = clip_ray(&target.planes, origin, direction) {
+                if nearest.is_none_or(|hit| distance < hit.distance as f64) {
+                    let face_hit = hit(
+                        face,
+                        &target.projections[face],
+                        &target.planes[face],
+                        origin,
+                        direction,
+                        distance,
+                    );
+                    nearest = Some(RayHit {
+                        entity: target.entity,
+                        brush: target.brush,
+                        ..face_hit
+                    });
+                }
+            }
+        }
+        nearest
+    }
+}
+
+/// The origin and normalized direction, `None` if there is no direction.
+pub(crate) fn ray(origin: [f32; 3], direction: [f32; 3]) -> Option<(DVec3, DVec3)> {
+    let direction = Vec3::from_array(direction).as_dvec3().try_normalize()?;
+    Some((Vec3::from_array(origin).as_dvec3(), direction))
+}
+
+/// Clips the ray by the planes of a convex brush. Returns the distance to where it enters the
+/// brush and the face it enters through.
+pub(crate) fn clip_ray(planes: &[Plane], origin: DVec3, direction: DVec3) -> Option<(f64, usize)> {
+    let mut enter = (f64::MIN, None);
+    let mut exit = f64::MAX;
+    for (i, plane) in planes.iter().enumerate() {
+        let distance = plane.distance(origin);
+        let speed = plane.normal.dot(direction);
+        if speed == 0.0 {
+            if distance > 0.0 {
+                return None;
+            }
+            continue;
+        }
+
+        let t = -distance / speed;
+        if speed < 0.0 {
+            if t > enter.0 {
+                enter = (t, Some(i));
+            }
+        } else {
+            exit = exit.min(t);
+        }
+    }
+
+    let (t, face) = enter;
+    if t < 0.0 || t > exit {
+        return None;
+    }
+    Some((t, face?))
+}
+
+/// Distance to where the ray enters the box, 0 if it starts inside.
+pub(crate) fn enter_box(min: DVec3, max: DVec3, origin: DVec3, direction: DVec3) -> Option<f64> {
+    let inverse = direction.recip();
+    let (t1, t2) = ((min - origin) * inverse, (max - origin) * inverse);
+    let enter = t1.min(t2).max_element().max(0.0);
+    let exit = t1.max(t2).min_element();
+    (enter <= exit).then_some(enter)
+}
+
+pub(crate) fn hit(
+    face: usize,
+    projection: &Projection,
+    plane: &Plane,
+    origin: DVec3,
+    direction: DVec3,
+    distance: f64,
+) -> RayHit {
+    let point = (origin + direction * distance).as_vec3();
+    RayHit {
+        distance: distance as f32,
+        entity: 0,
+        brush: 0,
+        face,
+        point: point.to_array(),
+        normal: plane.normal.as_vec3().to_array(),
+        uv: projection.uv(point).to_array(),
+    }
+}
+
+/// The texture projection of a face, with the axes divided by the scale.
+#[derive(Debug)]
+pub(crate) struct Projection {
+    axis_u: Vec3,
+    axis_v: Vec3,
+    offset: Vec2,
+}
+
+impl Projection {
+    pub(crate) fn new(face: &Face) -> Self {
+        Self {
+            axis_u: Vec3::from_array(face.axis_u) / face.scale[0],
+            axis_v: Vec3::from_array(face.axis_v) / face.scale[1],
+            offset: Vec2::from_array(face.offset),
+        }
+    }
+
+    fn uv(&self, point: Vec3) -> Vec2 {
+        Vec2::new(point.dot(self.axis_u), point.dot(self.axis_v)) + self.offset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MapBuilder;
+
+    fn map() -> Map {
+        MapBuilder::new()
+            .brush(Brush::cuboid([-64.0, -64.0, -16.0], [64.0, 64.0, 0.0], "FLOOR").unwrap())
+            .brush(Brush::wedge([0.0, -64.0, 0.0], [64.0, 64.0, 64.0], "RAMP").unwrap())
+            .brush_entity(
+                "func_door",
+                &[],
+                vec![Brush::cuboid([-32.0, -8.0, 0.0], [-16.0, 8.0, 64.0], "DOOR").unwrap()],
+            )
+            .build()
+    }
+
+    #[test]
+    fn test_brush() {
+        let floor = Brush::cuboid([-64.0, -64.0, -16.0], [64.0, 64.0, 0.0], "FLOOR").unwrap();
+        let hit = floor.cast_ray([8.0, 4.0, 32.0], [0.0, 0.0, -2.0]).unwrap();
+        assert_eq!(hit.distance, 32.0);
+        assert_eq!(hit.point, [8.0, 4.0, 0.0]);
+        assert_eq!(hit.normal, [0.0, 0.0, 1.0]);
+        assert_eq!(floor.faces[hit.face].triangle[0][2], 0.0);
+        // world aligned axes X and -Y
+        assert_eq!(hit.uv, [8.0, -4.0]);
+
+        assert_eq!(floor.cast_ray([8.0, 4.0, 32.0], [0.0, 0.0, 1.0]), None);
+        assert_eq!(floor.cast_ray([100.0, 4.0, 32.0], [0.0, 0.0, -1.0]), None);
+        // starting inside
+        assert_eq!(floor.cast_ray([8.0, 4.0, -8.0], [0.0, 0.0, -1.0]), None);
+        assert_eq!(floor.cast_ray([8.0, 4.0, 32.0], [0.0; 3]), None);
+    }
+
+    #[test]
+    fn test_map() {
+        let map = map();
+
+        // over the ramp, it's hit before the floor
+        let hit = map.cast_ray([32.0, 0.0, 100.0], [0.0, 0.0, -1.0]).unwrap();
+        assert_eq!((hit.entity, hit.brush, hit.face), (0, 1, 4));
+        assert!((hit.distance - 68.0).abs() < 1e-4);
+        let half = 0.5f32.sqrt();
+        assert!(Vec3::from_array(hit.normal).abs_diff_eq(Vec3::new(-half, 0.0, half), 1e-5));
+
+        let hit = map.cast_ray([-100.0, 0.0, 32.0], [1.0, 0.0, 0.0]).unwrap();
+        assert_eq!((hit.entity, hit.brush, hit.distance), (1, 0, 68.0));
+
+        let hit = map.cast_ray([-40.0, 0.0, 32.0], [0.0, 0.0, -1.0]).unwrap();
+        assert_eq!((hit.entity, hit.brush, hit.distance), (0, 0, 32.0));
+
+        assert_eq!(map.cast_ray([-40.0, 0.0, 32.0], [0.0, 0.0, 1.0]), None);
+    }
+
+    #[test]
+    fn test_ray_caster() {
+        let map = map();
+        let mut map = map;
+        // never hit, but doesn't keep the caster from being built
+        let mut degenerate = Brush::cuboid([-8.0; 3], [8.0; 3], "BOX").unwrap();
+        degenerate.faces[0].triangle = [[0.0; 3]; 3];
+        map.entities[1].brushes.push(degenerate);
+
+        let caster = RayCaster::new(&map);
+        for (origin, direction) in [
+            ([32.0, 0.0, 100.0], [0.0, 0.0, -1.0]),
+            ([-100.0, 0.0, 32.0], [1.0, 0.0, 0.0]),
+            ([-40.0, 0.0, 32.0], [0.0, 0.0, -1.0]),
+            ([-40.0, 0.0, 32.0], [0.0, 0.0, 1.0]),
+            ([-100.0, 30.0, 50.0], [3.0, -1.0, -2.0]),
+            ([0.0, 0.0, 4.0], [0.0, 0.0, -1.0]),
+        ] {
+            assert_eq!(
+                caster.cast_ray(origin, direction),
+                map.cast_ray(origin, direction)
+            );
+        }
+
+        // the door is left out
+        let caster = RayCaster::with_filter(&map, |i, _| i == 0);
+        let hit = caster
+            .cast_ray([-100.0, 0.0, 32.0], [1.0, 0.0, 0.0])
+            .unwrap();
+        assert_eq!((hit.entity, hit.brush), (0, 1));
+    }
+
+    #[test]
+    fn test_open_brush() {
+        // a box without its top, which contains nothing
+        let mut open = Brush::cuboid([-56.0, -56.0, 8.0], [-40.0, -40.0, 24.0], "BOX").unwrap();
+        open.faces
+            .retain(|face| face.triangle.iter().any(|p| p[2] != 24.0));
+        assert!(!open.contains_point([-48.0, -48.0, 16.0]));
+        assert_eq!(open.cast_ray([-48.0, -48.0, 4.0], [0.0, 0.0, 1.0]), None);
+
+        let mut map = map();
+        map.entities[1].brushes.push(open);
+        let caster = RayCaster::new(&map);
+        for (origin, direction) in [
+            ([-48.0, -48.0, 4.0], [0.0, 0.0, 1.0]),
+            ([-100.0, -48.0, 16.0], [1.0, 0.0, 0.0]),
+        ] {
+            let hit = map.cast_ray(origin, direction);
+            assert!(hit.is_none_or(|hit| (hit.entity, hit.brush) != (1, 1)));
+            assert_eq!(caster.cast_ray(origin, direction), hit);
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use crate::{Brush, Entity, Face, Map};
+use glam::{EulerRot, Mat4, Quat, Vec2, Vec3};
+
+/// Whether transforming a brush carries its textures along.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TextureLock {
+    /// The texture axes and offsets stay as they are, so textures stay fixed in the world and
+    /// slide over the moved faces. Faces that turned may end up with stretched textures.
+    #[default]
+    Off,
+    /// The texture axes, offsets and scales follow the transform, so every point of a face keeps
+    /// its texture coordinates.
+    On,
+}
+
+impl Face {
+    /// Applies `matrix` to the plane points, and to the texture projection if `lock` is on.
+    pub fn transform(&mut self, matrix: Mat4, lock: TextureLock) {
+        let mut triangle = self.triangle.map(|p| {
+            matrix
+                .transform_point3(Vec3::from_array(p))
+                .to_array()
+                .map(snap)
+        });
+        if matrix.determinant() < 0.0 {
+            // a mirrored triangle would turn the face inside out
+            triangle.swap(1, 2);
+        }
+        self.triangle = triangle;
+
+        if lock == TextureLock::On {
+            self.lock_texture(matrix);
+        }
+    }
+
+    /// Moves the texture projection along with `matrix`. With `u = p · axis_u / scale_u + offset_u`,
+    /// the projection of the transformed point `M p` is the same as the old one of `p` when the
+    /// scaled axes are multiplied by the inverse transpose of `M` and the offsets compensate for
+    /// its translation.
+    fn lock_texture(&mut self, matrix: Mat4) {
+        let inverse_transpose = matrix.inverse().transpose();
+        let translation = matrix.w_axis.truncate();
+
+        let mut offset = Vec2::from_array(self.offset);
+        for (n, axis) in [&mut self.axis_u, &mut self.axis_v].into_iter().enumerate() {
+            let scaled = Vec3::from_array(*axis) / self.scale[n];
+            let moved = inverse_transpose.transform_vector3(scaled);
+            offset[n] -= translation.dot(moved);
+
+            let scale = self.scale[n].signum() / moved.length();
+            *axis = (moved * scale).to_array();
+            self.scale[n] = scale;
+        }
+        self.offset = offset.to_array();
+    }
+}
+
+impl Brush {
+    /// Applies `matrix` to the points of all faces. Mirroring transforms keep the faces pointing
+    /// outward.
+    pub fn transform(&mut self, matrix: Mat4, lock: TextureLock) {
+        for face in &mut self.faces {
+            face.transform(matrix, lock);
+        }
+    }
+}
+
+impl Entity {
+    /// Transforms the brushes and updates the `origin`, `angle` and `angles` keys. Entities
+    /// without brushes get an orientation key when they are rotated.
+    pub fn transform(&mut self, matrix: Mat4, lock: TextureLock) {
+        for brush in &mut self.brushes {
+            brush.transform(matrix, lock);
+        }
+
+        if let Some(origin) = self.vector("origin") {
+            let origin = matrix.transform_point3(Vec3::from_array(origin));
+            self.properties.insert(
+                "origin".to_string(),
+                format_vector(origin.to_array().map(snap)),
+            );
+        }
+
+        let (_, rotation, _) = matrix.to_scale_rotation_translation();
+        if rotation.abs_diff_eq(Quat::IDENTITY, 1e-6) {
+            return;
+        }
+
+        let has_angles = self.properties.contains_key("angles");
+        let has_angle = self.properties.contains_key("angle");
+        if !has_angles && !has_angle && !self.brushes.is_empty() {
+            return;
+        }
+
+        let (yaw, pitch, roll) = (rotation * self.orientation()).to_euler(EulerRot::ZYX);
+        let [pitch, yaw, roll] = [pitch, yaw, roll].map(|a| snap(a.to_degrees()));
+
+        if has_angles || pitch != 0.0 || roll != 0.0 {
+            self.properties.remove("angle");
+            self.properties
+                .insert("angles".to_string(), format_vector([pitch, yaw, roll]));
+        } else {
+            self.properties
+                .insert("angle".to_string(), format_number(yaw));
+        }
+    }
+
+    /// The rotation given by `angles` as pitch, yaw and roll in degrees, or by the yaw in `angle`
+    /// where -1 and -2 point up and down.
+    fn orientation(&self) -> Quat {
+        let [pitch, yaw, roll] = match self.vector("angles") {
+            Some(angles) => angles,
+            None => match self.properties.get("angle").and_then(|a| a.parse().ok()) {
+                Some(-1.0) => [-90.0, 0.0, 0.0],
+                Some(-2.0) => [90.0, 0.0, 0.0],
+                Some(yaw) => [0.0, yaw, 0.0],
+                None => [0.0; 3],
+            },
+        };
+        Quat::from_euler(
+            EulerRot::ZYX,
+            yaw.to_radians(),
+            pitch.to_radians(),
+            roll.to_radians(),
+        )
+    }
+
+    /// Reads a key of three whitespace separated numbers, like `origin` or `angles`.
+    pub(crate) fn vector(&self, key: &str) -> Option<[f32; 3]> {
+        let values = self
+            .properties
+            .get(key)?
+            .split_whitespace()
+            .map(|v| v.parse().ok())
+            .collect::<Option<Vec<f32>>>()?;
+        values.try_into().ok()
+    }
+}
+
+impl Map {
+    /// Transforms every entity, see [Entity::transform].
+    pub fn transform(&mut self, matrix: Mat4, lock: TextureLock) {
+        for entity in &mut self.entities {
+            entity.transform(matrix, lock);
+        }
+    }
+}
+
+/// Rounds values within rounding error of an integer, so transformed integer points stay exact.
+fn snap(value: f32) -> f32 {
+    let rounded = value.round();
+    if (value - rounded).abs() < 1e-3 {
+        rounded + 0.0
+    } else {
+        value
+    }
+}
+
+fn format_number(value: f32) -> String {
+    format!("{}", value)
+}
+
+fn format_vector(vector: [f32; 3]) -> String {
+    vector.map(format_number).join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_str;
+    use crate::meshing::ToMesh;
+
+    const MAP: &str = r#"
+{
+"classname" "worldspawn"
+{
+( -16 -64 -16 ) ( -16 -63 -16 ) ( -16 -64 -15 ) WALL [ 0 -1 0 3 ] [ 0 0 -1 5 ] 0 0.5 2
+( -64 -16 -16 ) ( -64 -16 -15 ) ( -63 -16 -16 ) WALL [ 1 0 0 3 ] [ 0 0 -1 5 ] 0 0.5 2
+( -64 -64 -16 ) ( -63 -64 -16 ) ( -64 -63 -16 ) WALL [ -1 0 0 3 ] [ 0 -1 0 5 ] 0 0.5 2
+( 64 64 16 ) ( 64 65 16 ) ( 65 64 16 ) WALL [ 1 0 0 3 ] [ 0 -1 0 5 ] 0 0.5 2
+( 64 16 16 ) ( 65 16 16 ) ( 64 16 17 ) WALL [ -1 0 0 3 ] [ 0 0 -1 5 ] 0 0.5 2
+( 16 64 16 ) ( 16 64 17 ) ( 16 65 16 ) WALL [ 0 1 0 3 ] [ 0 0 -1 5 ] 0 0.5 2
+}
+}
+{
+"classname" "info_player_start"
+"origin" "32 0 24"
+"angle" "90"
+}
+"#;
+
+    /// The texture coordinates of the corners of every triangle, keyed by their rounded
+    /// positions.
+    fn uvs(mesh: &crate::Mesh, matrix: Mat4) -> Vec<([i32; 3], [i32; 2])> {
+        let inverse = matrix.inverse();
+        let mut uvs = mesh
+            .positions
+            .iter()
+            .zip(&mesh.uvs)
+            .map(|(p, uv)| {
+                let original = inverse.transform_point3(Vec3::from_array(*p));
+                (
+                    original.round().as_ivec3().to_array(),
+                    (Vec2::from_array(*uv) * 1024.0)
+                        .round()
+                        .as_ivec2()
+                        .to_array(),
+                )
+            })
+            .collect::<Vec<_>>();
+        uvs.sort();
+        uvs
+    }
+
+    #[test]
+    fn test_texture_lock() {
+        let map = from_str(MAP).unwrap();
+        let original = map.entities[0].to_mesh().unwrap();
+
+        let matrices = [
+            Mat4::from_translation(Vec3::new(13.0, -7.0, 32.0)),
+            Mat4::from_rotation_z(90f32.to_radians()),
+            Mat4::from_axis_angle(Vec3::new(1.0, 2.0, 3.0).normalize(), 0.7)
+                * Mat4::from_translation(Vec3::new(5.0, 0.0, 0.0)),
+            Mat4::from_scale(Vec3::new(2.0, 1.0, 0.5)),
+            Mat4::from_scale(Vec3::new(-1.0, 1.0, 1.0)),
+        ];
+        for matrix in matrices {
+            let mut map = from_str(MAP).unwrap();
+            map.transform(matrix, TextureLock::On);
+            assert!(map.entities[0].brushes[0].validate().is_empty());
+
+            let mesh = map.entities[0].to_mesh().unwrap();
+            assert_eq!(uvs(&mesh, matrix), uvs(&original, Mat4::IDENTITY));
+
+            // the axes stay normalized, the scales take up the stretch
+            for face in &map.entities[0].brushes[0].faces {
+                assert!((Vec3::from_array(face.axis_u).length() - 1.0).abs() < 1e-5);
+                assert!(face.scale[0] > 0.0 && face.scale[1] > 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_texture_lock_off() {
+        let mut map = from_str(MAP).unwrap();
+        map.transform(
+            Mat4::from_translation(Vec3::new(16.0, 0.0, 0.0)),
+            TextureLock::Off,
+        );
+        let face = &map.entities[0].brushes[0].faces[0];
+        assert_eq!(face.triangle[0], [0.0, -64.0, -16.0]);
+        assert_eq!(face.axis_u, [0.0, -1.0, 0.0]);
+        assert_eq!(face.offset, [3.0, 5.0]);
+    }
+
+    #[test]
+    fn test_entity_keys() {
+        let mut map = from_str(MAP).unwrap();
+        map.transform(Mat4::from_rotation_z(90f32.to_radians()), TextureLock::On);
+        let player = &map.entities[1].properties;
+        assert_eq!(player["origin"], "0 32 24");
+        assert_eq!(player["angle"], "180");
+
+        let mut map = from_str(MAP).unwrap();
+        map.transform(Mat4::from_rotation_x(-30f32.to_radians()), TextureLock::On);
+        let player = &map.entities[1].properties;
+        assert!(!player.contains_key("angle"));
+        assert_eq!(player["angles"], "30 90 0");
+
+        // worldspawn has brushes and no orientation, it doesn't get one
+        assert!(!map.entities[0].properties.contains_key("angles"));
+    }
+}
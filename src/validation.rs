@@ -0,0 +1,317 @@
+use crate::geometry::volume_and_centroid;
+use crate::meshing::{face_winding, is_bounded, utils::ArrayExt, Epsilons, Plane};
+use crate::{Brush, Entity, Map};
+use glam::Vec3;
+use std::fmt;
+
+/// Something wrong with a brush or one of its faces, as found by [Brush::validate].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// The three points of the face are collinear, so they don't define a plane.
+    DegeneratePlane,
+    /// The face lies in the same plane as the face `other`.
+    DuplicatePlane { other: usize },
+    /// The face lies in the same plane as the face `other`, but faces the opposite way.
+    OppositePlane { other: usize },
+    /// The face is clipped away entirely by the other faces and contributes no polygon.
+    RedundantFace,
+    /// The face isn't enclosed by the other faces, so the brush is open on that side.
+    Unbounded,
+    /// The faces of the brush don't enclose any volume.
+    ZeroVolume,
+    /// One of the texture scales is zero.
+    ZeroTextureScale,
+    /// `axis_u` and `axis_v` are parallel or zero, so the texture can't be projected.
+    ParallelTextureAxes,
+}
+
+impl fmt::Display for DiagnosticKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DegeneratePlane => write!(f, "degenerate plane"),
+            Self::DuplicatePlane { other } => write!(f, "same plane as face {}", other),
+            Self::OppositePlane { other } => write!(f, "opposite plane of face {}", other),
+            Self::RedundantFace => write!(f, "redundant face"),
+            Self::Unbounded => write!(f, "brush is not closed"),
+            Self::ZeroVolume => write!(f, "brush has no volume"),
+            Self::ZeroTextureScale => write!(f, "zero texture scale"),
+            Self::ParallelTextureAxes => write!(f, "parallel texture axes"),
+        }
+    }
+}
+
+/// A problem found while validating a map, located by the index of the entity, the brush within
+/// the entity and, unless the problem concerns the whole brush, the face within the brush.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub entity: usize,
+    pub brush: usize,
+    pub face: Option<usize>,
+    pub kind: DiagnosticKind,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "entity {}, brush {}", self.entity, self.brush)?;
+        if let Some(face) = self.face {
+            write!(f, ", face {}", face)?;
+        }
+        write!(f, ": {}", self.kind)
+    }
+}
+
+impl Brush {
+    /// Checks the brush for degenerate or redundant faces, missing volume and broken texture
+    /// axes. The diagnostics have `entity` and `brush` set to 0, use [Map::validate] to have them
+    /// filled in.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        self.validate_with(&Epsilons::default())
+    }
+
+    pub fn validate_with(&self, epsilons: &Epsilons) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        let mut report = |face, kind| {
+            diagnostics.push(Diagnostic {
+                entity: 0,
+                brush: 0,
+                face,
+                kind,
+            })
+        };
+
+        let mut planes = Vec::new();
+        for (i, face) in self.faces.iter().enumerate() {
+            match Plane::from_triangle(face.triangle) {
+                Some(plane) => planes.push((i, plane)),
+                None => report(Some(i), DiagnosticKind::DegeneratePlane),
+            }
+
+            if face.scale[0] == 0.0 || face.scale[1] == 0.0 {
+                report(Some(i), DiagnosticKind::ZeroTextureScale);
+            }
+
+            let axis_u = Vec3::from_array(face.axis_u).normalize_or_zero();
+            let axis_v = Vec3::from_array(face.axis_v).normalize_or_zero();
+            if axis_u.cross(axis_v).length() < 1e-6 {
+                report(Some(i), DiagnosticKind::ParallelTextureAxes);
+            }
+        }
+
+        let epsilon = epsilons.plane as f64;
+        let mut duplicates = Vec::new();
+        for pair in planes.combs(2) {
+            let ((i, a), (j, b)) = (pair[0], pair[1]);
+            if a.coincides(b, epsilon) {
+                report(Some(*j), DiagnosticKind::DuplicatePlane { other: *i });
+                duplicates.push(*j);
+            } else if a.normal.dot(b.normal) < -1.0 + 1e-9 && a.distance(b.origin).abs() <= epsilon
+            {
+                report(Some(*j), DiagnosticKind::OppositePlane { other: *i });
+            }
+        }
+
+        let (indices, planes) = planes.into_iter().unzip::<_, _, Vec<_>, Vec<_>>();
+        let mut windings = Vec::new();
+        let mut bounded = true;
+        for (n, i) in indices.iter().enumerate() {
+            match face_winding(&planes, n, epsilons) {
+                Some(winding) if !is_bounded(&winding) => {
+                    report(Some(*i), DiagnosticKind::Unbounded);
+                    bounded = false;
+                }
+                Some(winding) => windings.push(winding),
+                None if duplicates.contains(i) => {}
+                None => report(Some(*i), DiagnosticKind::RedundantFace),
+            }
+        }
+
+        if bounded
+            && (windings.len() < 4
+                || volume_and_centroid(&windings).0 <= (epsilons.vertex as f64).powi(3))
+        {
+            report(None, DiagnosticKind::ZeroVolume);
+        }
+
+        diagnostics
+    }
+}
+
+impl Entity {
+    /// Validates all brushes of the entity, see [Brush::validate]. The diagnostics have `entity`
+    /// set to 0.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        self.validate_with(&Epsilons::default())
+    }
+
+    pub fn validate_with(&self, epsilons: &Epsilons) -> Vec<Diagnostic> {
+        self.brushes
+            .iter()
+            .enumerate()
+            .flat_map(|(i, brush)| {
+                brush
+                    .validate_with(epsilons)
+                    .into_iter()
+                    .map(move |d| Diagnostic { brush: i, ..d })
+            })
+            .collect()
+    }
+}
+
+impl Map {
+    /// Validates all brushes in the map, see [Brush::validate].
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        self.validate_with(&Epsilons::default())
+    }
+
+    pub fn validate_with(&self, epsilons: &Epsilons) -> Vec<Diagnostic> {
+        self.entities
+            .iter()
+            .enumerate()
+            .flat_map(|(i, entity)| {
+                entity
+                    .validate_with(epsilons)
+                    .into_iter()
+                    .map(move |d| Diagnostic { entity: i, ..d })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::from_str;
+    use crate::meshing::utils::CUBE;
+
+    fn map(brushes: &[&str]) -> Map {
+        let brushes = brushes
+            .iter()
+            .map(|faces| format!("{{\n{}\n}}", faces))
+            .collect::<Vec<_>>()
+            .join("\n");
+        from_str(&format!(
+            "{{\n\"classname\" \"worldspawn\"\n}}\n{{\n\"classname\" \"func_wall\"\n{}\n}}",
+            brushes
+        ))
+        .unwrap()
+    }
+
+    fn kinds(faces: &str) -> Vec<(Option<usize>, DiagnosticKind)> {
+        map(&[faces]).entities[1].brushes[0]
+            .validate()
+            .into_iter()
+            .map(|d| (d.face, d.kind))
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_brush() {
+        assert_eq!(kinds(CUBE), vec![]);
+    }
+
+    #[test]
+    fn test_degenerate_plane() {
+        let faces = CUBE.replacen(
+            "( 16 16 16 ) ( 16 17 16 ) ( 17 16 16 )",
+            "( 16 16 16 ) ( 16 17 16 ) ( 16 18 16 )",
+            1,
+        );
+        assert_eq!(
+            kinds(&faces),
+            vec![
+                (Some(3), DiagnosticKind::DegeneratePlane),
+                (Some(0), DiagnosticKind::Unbounded),
+                (Some(1), DiagnosticKind::Unbounded),
+                (Some(4), DiagnosticKind::Unbounded),
+                (Some(5), DiagnosticKind::Unbounded),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_duplicate_and_redundant_faces() {
+        let faces = format!(
+            "{}\n{}\n{}",
+            CUBE,
+            "( 0 0 16 ) ( 0 1 16 ) ( 1 0 16 ) A [ 1 0 0 0 ] [ 0 -1 0 0 ] 0 1 1",
+            "( 32 32 32 ) ( 32 33 32 ) ( 33 32 32 ) A [ 1 0 0 0 ] [ 0 -1 0 0 ] 0 1 1"
+        );
+        assert_eq!(
+            kinds(&faces),
+            vec![
+                (Some(6), DiagnosticKind::DuplicatePlane { other: 3 }),
+                (Some(7), DiagnosticKind::RedundantFace),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_zero_volume() {
+        let faces = CUBE.replacen(
+            "( 16 16 16 ) ( 16 17 16 ) ( 17 16 16 )",
+            "( 16 16 -16 ) ( 16 17 -16 ) ( 17 16 -16 )",
+            1,
+        );
+        assert_eq!(
+            kinds(&faces),
+            vec![
+                (Some(3), DiagnosticKind::OppositePlane { other: 2 }),
+                (Some(0), DiagnosticKind::RedundantFace),
+                (Some(1), DiagnosticKind::RedundantFace),
+                (Some(4), DiagnosticKind::RedundantFace),
+                (Some(5), DiagnosticKind::RedundantFace),
+                (None, DiagnosticKind::ZeroVolume),
+            ]
+        );
+
+        // small, but well above the vertex epsilon cubed
+        let small = Brush::cuboid([0.0; 3], [0.2; 3], "SMALL").unwrap();
+        assert_eq!(small.validate(), vec![]);
+    }
+
+    #[test]
+    fn test_texture_axes() {
+        let faces = CUBE
+            .replacen(
+                "[ 0 -1 0 0 ] [ 0 0 -1 0 ] 0 1 1",
+                "[ 0 -1 0 0 ] [ 0 1 0 0 ] 0 1 1",
+                1,
+            )
+            .replacen(
+                "[ 1 0 0 0 ] [ 0 0 -1 0 ] 0 1 1",
+                "[ 1 0 0 0 ] [ 0 0 -1 0 ] 0 0 1",
+                1,
+            );
+        assert_eq!(
+            kinds(&faces),
+            vec![
+                (Some(0), DiagnosticKind::ParallelTextureAxes),
+                (Some(1), DiagnosticKind::ZeroTextureScale),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_map_validate() {
+        let open = CUBE.lines().take(5).collect::<Vec<_>>().join("\n");
+        let diagnostics = map(&[CUBE, &open]).validate();
+        assert!(diagnostics
+            .iter()
+            .all(|d| d.entity == 1 && d.brush == 1 && d.kind == DiagnosticKind::Unbounded));
+        assert_eq!(
+            diagnostics[0].to_string(),
+            "entity 1, brush 1, face 1: brush is not closed"
+        );
+
+        let loose = Epsilons {
+            plane: 0.001,
+            vertex: 0.5,
+        };
+        let small = map(&[&CUBE.replace("16", "0.2")]);
+        assert!(small.validate().is_empty());
+        assert!(small
+            .validate_with(&loose)
+            .iter()
+            .any(|d| d.kind == DiagnosticKind::ZeroVolume));
+    }
+}